@@ -0,0 +1,582 @@
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::breaker::Breakers;
+use crate::budget::{max_fee_msat, Budget, Reservation, Spend};
+use crate::filter::TargetFilter;
+use crate::journal::{Journal, JournalEntry, JournalResult};
+use crate::metrics::Metrics;
+use crate::{random_in_range, Action, Amount, Channel, Config, LightningBackend, Node, Peer};
+
+/// Everything a single run of an action gets to work with.
+pub struct ActionContext {
+    /// Name of the action being run
+    pub action: String,
+    /// The node to act on
+    pub client: Arc<dyn LightningBackend>,
+    /// When set, targets, spend and closes are counted here
+    pub metrics: Option<Arc<Metrics>>,
+    /// Snapshot of the config taken when the run started
+    pub config: Config,
+    /// Forked from the action's seeded stream; use it for every random choice
+    pub rng: StdRng,
+    pub breakers: Arc<Breakers>,
+    pub budget: Arc<Budget>,
+    pub journal: Arc<Journal>,
+}
+
+impl ActionContext {
+    /// Gossip about every node, keyed by pubkey, if `filter` needs it
+    pub(crate) async fn gossip(&self, filter: &TargetFilter<'_>) -> Result<BTreeMap<String, Node>, Error> {
+        if !filter.needs_gossip() {
+            return Ok(BTreeMap::new())
+        }
+        Ok(self.client.list_nodes().await?
+            .into_iter()
+            .map(|n| (n.nodeid.to_string(), n))
+            .collect())
+    }
+
+    /// Nodes from the graph that the target allow/deny lists let us act on
+    pub async fn nodes(&self) -> Result<Vec<Node>, Error> {
+        let nodes = self.client.list_nodes().await?;
+        Ok(TargetFilter::new(&self.config).nodes(nodes))
+    }
+
+    /// Peers that the target allow/deny lists let us act on
+    pub async fn peers(&self) -> Result<Vec<Peer>, Error> {
+        let filter = TargetFilter::new(&self.config);
+        let peers = self.client.list_peers().await?;
+        Ok(filter.peers(peers, &self.gossip(&filter).await?))
+    }
+
+    /// Our channels with peers that the target allow/deny lists let us act
+    /// on, minus protected channels.  Channel-level actions must pick from
+    /// this rather than `LightningBackend::list_channels`.
+    pub async fn channels(&self) -> Result<Vec<Channel>, Error> {
+        let filter = TargetFilter::new(&self.config);
+        let channels = self.client.list_channels().await?;
+        let protected = self.protected_channels().await?;
+        Ok(filter.channels(channels, &self.gossip(&filter).await?)
+            .into_iter()
+            .filter(|c| match &c.short_channel_id {
+                Some(scid) if protected.contains(scid) => {
+                    log::debug!("Skipping protected channel {}", scid);
+                    false
+                }
+                _ => true,
+            })
+            .collect())
+    }
+
+    /// Protected short channel ids from both the config and the datastore
+    pub async fn protected_channels(&self) -> Result<Vec<String>, Error> {
+        let mut protected = self.client.protected_channels().await?;
+        protected.extend(self.config.protected_channels.iter().cloned());
+        Ok(protected)
+    }
+
+    /// Bumps a counter, if metrics are being collected
+    pub fn metric(&self, name: &'static str, labels: &[(&str, &str)], by: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.inc(name, labels, by);
+        }
+    }
+
+    /// Settles `reservation` with what was actually spent and counts it in
+    /// the matching metrics.  Dry runs spend nothing, so they release the
+    /// reservation and count nothing.
+    pub fn settle(&self, reservation: Reservation, spent: &[(Spend, u64)]) {
+        if self.client.dry_run() {
+            return
+        }
+        for (spend, amount) in spent {
+            match spend {
+                Spend::SentMsat => self.metric("spaz_sent_msat_total", &[], *amount),
+                Spend::FeesMsat => self.metric("spaz_fees_msat_total", &[], *amount),
+                Spend::OpenSat => self.metric("spaz_channels_opened_total", &[], 1),
+            }
+        }
+        reservation.settle(spent);
+    }
+
+    /// Counts a closed channel, unless the close was only recorded by a dry run
+    pub fn record_close(&self) {
+        if !self.client.dry_run() {
+            self.metric("spaz_channels_closed_total", &[], 1);
+        }
+    }
+
+    fn journal_skip(&self, target: &str, amount: Option<u64>, reason: String) {
+        self.metric("spaz_action_targets_total", &[("action", &self.action), ("result", "skipped")], 1);
+        let mut entry = JournalEntry::new(&self.action, Some(target), JournalResult::Skipped);
+        entry.amount = amount;
+        entry.error = Some(reason);
+        entry.dry_run = self.client.dry_run();
+        self.journal.append(self.client.clone(), &self.config, entry);
+    }
+
+    /// Whether this action's circuit breaker for `target` is open.  Actions
+    /// should skip suspended targets rather than retrying them every run.
+    pub fn target_suspended(&self, target: &str) -> bool {
+        let suspended = self.breakers.is_suspended(&Breakers::key(&self.action, Some(target)));
+        if suspended {
+            log::debug!("Skipping suspended target {} for {}", target, self.action);
+            self.journal_skip(target, None, "circuit breaker open".to_string());
+        }
+        suspended
+    }
+
+    /// Reserves `amounts` for acting on `target` if they are all within
+    /// budget, and journals a skip if not.  Hold the reservation until the
+    /// call is done, then `settle` it; dropping it on failure releases it.
+    pub fn reserve(&self, amounts: &[(Spend, u64)], target: &str) -> Option<Reservation> {
+        match self.budget.reserve(amounts, &self.config) {
+            Ok(reservation) => Some(reservation),
+            Err(spend) => {
+                let amount = amounts.iter().find(|(s, _)| *s == spend).map(|(_, a)| *a);
+                self.journal_skip(target, amount, format!("{:?} budget exhausted", spend));
+                None
+            }
+        }
+    }
+
+    /// The worst case a keysend or poke of `amount_msat` can spend
+    pub fn payment_amounts(amount_msat: u64) -> [(Spend, u64); 2] {
+        [(Spend::SentMsat, amount_msat), (Spend::FeesMsat, max_fee_msat(amount_msat))]
+    }
+
+    /// Feeds the outcome of acting on `target` into its circuit breaker and the journal
+    pub fn record_target<T>(&self, target: &str, amount: Option<u64>, params: serde_json::Value, result: &Result<T, Error>) {
+        let key = Breakers::key(&self.action, Some(target));
+        let mut entry = JournalEntry::new(&self.action, Some(target), JournalResult::Ok);
+        entry.amount = amount;
+        entry.params = params;
+        entry.dry_run = self.client.dry_run();
+        match result {
+            Ok(_) => self.breakers.record_success(&key),
+            Err(err) => {
+                self.breakers.record_failure(&key, &self.config);
+                entry.result = JournalResult::Error;
+                entry.error = Some(format!("{:#}", err));
+            }
+        }
+        let result = if result.is_ok() { "ok" } else { "error" };
+        self.metric("spaz_action_targets_total", &[("action", &self.action), ("result", result)], 1);
+        self.journal.append(self.client.clone(), &self.config, entry);
+    }
+}
+
+/// A chaos behavior.  Implement this and add it to a `Registry` to have spaz
+/// schedule it alongside the built-in actions.
+#[async_trait]
+pub trait ChaosAction: Send + Sync {
+    /// Unique name, used for config keys, RPCs and logging
+    fn name(&self) -> &str;
+
+    /// Chance of acting on each candidate (node, peer, channel...) during a run
+    fn probability(&self, config: &Config) -> f64;
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.action_enabled.get(self.name()).copied().unwrap_or(true)
+    }
+
+    fn set_enabled(&self, config: &mut Config, enabled: bool) {
+        config.action_enabled.insert(self.name().to_string(), enabled);
+    }
+
+    /// Mean number of seconds between runs
+    fn interval_secs(&self, config: &Config) -> f64 {
+        config.interval_secs
+    }
+
+    async fn run(&self, ctx: &mut ActionContext) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl ChaosAction for Action {
+    fn name(&self) -> &str {
+        Action::name(self)
+    }
+
+    fn probability(&self, config: &Config) -> f64 {
+        config.probability(*self)
+    }
+
+    fn enabled(&self, config: &Config) -> bool {
+        config.enabled(*self)
+    }
+
+    fn set_enabled(&self, config: &mut Config, enabled: bool) {
+        config.set_enabled(*self, enabled)
+    }
+
+    fn interval_secs(&self, config: &Config) -> f64 {
+        config.interval_secs(*self)
+    }
+
+    async fn run(&self, ctx: &mut ActionContext) -> Result<(), Error> {
+        match self {
+            Action::Open | Action::Close => manage_channel_count(ctx, *self).await,
+            Action::Fee => maybe_randomize_channel_fee(ctx).await,
+            Action::Keysend => maybe_keysend_random_node(ctx).await,
+            Action::Poke => maybe_poke_node(ctx).await,
+            Action::Disconnect => maybe_disconnect_random_peer(ctx).await,
+            Action::Ping => maybe_ping_peer_random_bytes(ctx).await,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActionInfo {
+    pub name: String,
+    pub enabled: bool,
+    pub probability: f64,
+    pub interval_secs: f64,
+}
+
+/// The set of actions spaz runs.  Actions are fixed once the plugin starts;
+/// enabling and disabling them goes through the config.
+#[derive(Default)]
+pub struct Registry {
+    actions: BTreeMap<String, Arc<dyn ChaosAction>>,
+}
+
+impl Registry {
+    /// A registry holding all of the built-in actions
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::default();
+        for action in Action::ALL {
+            registry.register(Arc::new(action)).unwrap();
+        }
+        registry
+    }
+
+    pub fn register(&mut self, action: Arc<dyn ChaosAction>) -> Result<(), Error> {
+        let name = action.name().to_string();
+        if self.actions.contains_key(&name) {
+            return Err(anyhow!("Action {} is already registered", name))
+        }
+        self.actions.insert(name, action);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn ChaosAction>, Error> {
+        self.actions.get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown action: {}.  Available: {:?}", name, self.names()))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.actions.keys().cloned().collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn ChaosAction>> {
+        self.actions.values()
+    }
+
+    pub fn list(&self, config: &Config) -> Vec<ActionInfo> {
+        self.iter()
+            .map(|a| ActionInfo {
+                name: a.name().to_string(),
+                enabled: a.enabled(config),
+                probability: a.probability(config),
+                interval_secs: a.interval_secs(config),
+            })
+            .collect()
+    }
+}
+
+pub async fn maybe_randomize_channel_fee(ctx: &mut ActionContext) -> Result<(), Error> {
+    let channels = ctx.channels().await?;
+    let probability = ctx.config.fee_probability;
+    let ppm_range = (ctx.config.fee_ppm_min, ctx.config.fee_ppm_max);
+    let base_range = (ctx.config.fee_base_min_msat, ctx.config.fee_base_max_msat);
+    for channel in channels {
+        if ctx.rng.gen::<f64>() < probability {
+            match channel.short_channel_id {
+                Some(id) if ctx.target_suspended(&id) => {},
+                Some(id) => {
+                    log::info!("Randomizing channel fee for {}", &id);
+                    let res = ctx.client.randomize_fee(&id, ppm_range, base_range, &mut ctx.rng).await;
+                    let params = res.as_ref().map(|(ppm, base)| json!({ "fee_ppm": ppm, "fee_base_msat": base })).unwrap_or_default();
+                    ctx.record_target(&id, None, params, &res);
+                    match res {
+                        Ok(_) => log::debug!("Successfully randomized fee"),
+                        Err(e) => log::error!("Error configuring channel: {:?}", e),
+                    }
+                },
+                None => {
+                    log::debug!("No scid, so not randomizing")
+                }
+            }
+
+        }
+    }
+    Ok(())
+}
+
+pub async fn maybe_disconnect_random_peer(ctx: &mut ActionContext) -> Result<(), Error> {
+    let peers = ctx.peers().await?;
+    let probability = ctx.config.disconnect_probability;
+    for peer in peers {
+        log::debug!("Peer under consideration: {:?}", peer);
+        if peer.connected && ctx.rng.gen::<f64>() < probability {
+            let target = peer.id.to_string();
+            if ctx.target_suspended(&target) {
+                continue
+            }
+            let res = ctx.client.disconnect_peer(peer.id).await;
+            ctx.record_target(&target, None, serde_json::Value::Null, &res);
+            res?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn maybe_ping_peer_random_bytes(ctx: &mut ActionContext) -> Result<(), Error> {
+    let peers = ctx.peers().await?;
+    let probability = ctx.config.ping_probability;
+    for peer in peers {
+        log::debug!("Peer under consideration: {:?}", peer);
+        if peer.connected && ctx.rng.gen::<f64>() < probability {
+            let target = peer.id.to_string();
+            if ctx.target_suspended(&target) {
+                continue
+            }
+            let res = ctx.client.random_ping_peer(peer.id, &mut ctx.rng).await;
+            ctx.record_target(&target, None, serde_json::Value::Null, &res);
+            res?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn maybe_keysend_random_node(ctx: &mut ActionContext) -> Result<(), Error> {
+    let nodes = ctx.nodes().await?;
+    let probability = ctx.config.keysend_probability;
+    let (min, max) = (ctx.config.keysend_amount_min_msat, ctx.config.keysend_amount_max_msat);
+    for node in nodes {
+        log::debug!("Node under consideration: {:?}", node);
+
+        if ctx.rng.gen::<f64>() < probability {
+            let amount: u64 = random_in_range(&mut ctx.rng, min, max);
+            let target = node.nodeid.to_string();
+            if ctx.target_suspended(&target) {
+                continue
+            }
+            let reservation = match ctx.reserve(&ActionContext::payment_amounts(amount), &target) {
+                Some(reservation) => reservation,
+                None => continue,
+            };
+            let res = ctx.client.keysend_node(node.nodeid, Amount::from_msat(amount)).await;
+            let params = res.as_ref().map(|p| json!({ "payment_hash": p.payment_hash.to_string() })).unwrap_or_default();
+            ctx.record_target(&target, Some(amount), params, &res);
+            match res {
+                Ok(payment) => {
+                    let sent = payment.amount_sent_msat.msat();
+                    ctx.settle(reservation, &[(Spend::SentMsat, sent), (Spend::FeesMsat, sent.saturating_sub(payment.amount_msat.msat()))]);
+                    log::info!("Successful keysend");
+                },
+                Err(err) => {
+                    log::warn!("Error doing keysend: {}", err);
+                }
+            }
+        }
+
+    }
+    Ok(())
+}
+
+pub async fn maybe_open_channel(ctx: &mut ActionContext) -> Result<(), Error> {
+    let nodes = ctx.nodes().await?;
+    let probability = ctx.config.open_probability;
+    let (min, max) = (ctx.config.open_amount_min_sat, ctx.config.open_amount_max_sat);
+    for node in nodes {
+        log::debug!("Perhaps open channel for node: {:?}", node);
+
+        if ctx.rng.gen::<f64>() < probability {
+
+            let amount: u64 = random_in_range(&mut ctx.rng, min, max);
+            let target = node.nodeid.to_string();
+            if ctx.target_suspended(&target) {
+                continue
+            }
+            let reservation = match ctx.reserve(&[(Spend::OpenSat, amount)], &target) {
+                Some(reservation) => reservation,
+                None => continue,
+            };
+            let res = ctx.client.open_channel_to_node(node, amount).await;
+            let params = res.as_ref().map(|txid| json!({ "txid": txid })).unwrap_or_default();
+            ctx.record_target(&target, Some(amount), params, &res);
+            match res {
+                Ok(_) => {
+                    ctx.settle(reservation, &[(Spend::OpenSat, amount)]);
+                    log::info!("Successfully opened channel");
+                },
+                Err(err) => {
+                    log::warn!("Error attempting to open channel: {}", err);
+                    return Err(err)
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn maybe_poke_node(ctx: &mut ActionContext) -> Result<(), Error> {
+    let nodes = ctx.nodes().await?;
+    let probability = ctx.config.poke_probability;
+    let (min, max) = (ctx.config.poke_amount_min_msat, ctx.config.poke_amount_max_msat);
+    for node in nodes {
+        log::debug!("Perhaps poke node: {:?}", node);
+
+        if ctx.rng.gen::<f64>() < probability {
+
+            let amount: u64 = random_in_range(&mut ctx.rng, min, max);
+            let target = node.nodeid.to_string();
+            if ctx.target_suspended(&target) {
+                continue
+            }
+            // Pokes lock up liquidity and route fees while in flight, so
+            // they reserve both, but they can never settle: only the route
+            // fees are charged
+            let reservation = match ctx.reserve(&ActionContext::payment_amounts(amount), &target) {
+                Some(reservation) => reservation,
+                None => continue,
+            };
+            let res = ctx.client.poke_node(node.nodeid, amount, &mut ctx.rng).await;
+            ctx.record_target(&target, Some(amount), serde_json::Value::Null, &res);
+            match res {
+                Ok(fees_msat) => {
+                    ctx.settle(reservation, &[(Spend::FeesMsat, fees_msat)]);
+                    log::info!("Successfully sent poke");
+                },
+                Err(err) => {
+                    log::warn!("Error attempting to poke node: {}", err);
+                    return Err(err)
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+pub async fn maybe_close_channel(ctx: &mut ActionContext) -> Result<(), Error> {
+    let channels = ctx.channels().await?;
+    let probability = ctx.config.close_probability;
+    for channel in channels {
+        log::debug!("May close this channel: {:?}", channel);
+
+        if ctx.rng.gen::<f64>() < probability {
+            match channel.short_channel_id {
+                Some(id) if ctx.target_suspended(&id) => {},
+                Some(id) => {
+                    let res = ctx.client.close_channel(&id).await;
+                    let params = res.as_ref().map(|txid| json!({ "txid": txid })).unwrap_or_default();
+                    ctx.record_target(&id, None, params, &res);
+                    match res {
+                        Ok(_) => {
+                            ctx.record_close();
+                            log::info!("Closed channel: {:?}", id);
+                        },
+                        Err(e) => {
+                            log::warn!("Error trying to close channel: {}", e);
+                        }
+                    }
+                },
+                None => {
+                    log::debug!("Unable to try to open channel, do not have alias")
+                }
+            }
+        }
+
+    }
+    Ok(())
+}
+
+pub async fn manage_channel_count(ctx: &mut ActionContext, action: Action) -> Result<(), Error> {
+    let channels = ctx.client.list_channels().await?;
+    match action {
+        Action::Open if channels.len() < 20 => maybe_open_channel(ctx).await,
+        Action::Close if channels.len() >= 20 => maybe_close_channel(ctx).await,
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dry_run::DryRunLog;
+    use crate::ClnClient;
+    use rand::SeedableRng;
+
+    fn context(dry_run: bool) -> ActionContext {
+        let mut client = ClnClient::new("lightning-rpc");
+        client.dry_run = dry_run.then(|| Arc::new(DryRunLog::default()));
+        let config = Config {
+            budget_sent_msat_per_day: 10_000,
+            budget_open_sat_per_day: 1_000_000,
+            journal: String::new(),
+            ..Config::default()
+        };
+        ActionContext {
+            action: "keysend".to_string(),
+            client: Arc::new(client),
+            metrics: Some(Arc::new(Metrics::default())),
+            config,
+            rng: StdRng::seed_from_u64(0),
+            breakers: Arc::new(Breakers::default()),
+            budget: Arc::new(Budget::default()),
+            journal: Arc::new(Journal::default()),
+        }
+    }
+
+    fn counted(ctx: &ActionContext, name: &str) -> bool {
+        ctx.metrics.as_ref().unwrap().render(&[]).lines().any(|l| l.starts_with(name))
+    }
+
+    #[test]
+    fn dry_runs_spend_nothing() {
+        let ctx = context(true);
+        let reservation = ctx.reserve(&[(Spend::SentMsat, 10_000), (Spend::OpenSat, 500_000)], "02aa").unwrap();
+        ctx.settle(reservation, &[(Spend::SentMsat, 10_000), (Spend::OpenSat, 500_000)]);
+        ctx.record_close();
+        assert_eq!(ctx.budget.remaining(Spend::SentMsat, &ctx.config), Some(10_000));
+        assert_eq!(ctx.budget.remaining(Spend::OpenSat, &ctx.config), Some(1_000_000));
+        for name in ["spaz_sent_msat_total", "spaz_channels_opened_total", "spaz_channels_closed_total"] {
+            assert!(!counted(&ctx, name), "{} was counted", name);
+        }
+    }
+
+    #[test]
+    fn real_runs_are_charged() {
+        let ctx = context(false);
+        let reservation = ctx.reserve(&[(Spend::SentMsat, 10_000), (Spend::OpenSat, 500_000)], "02aa").unwrap();
+        ctx.settle(reservation, &[(Spend::SentMsat, 10_000), (Spend::OpenSat, 500_000)]);
+        ctx.record_close();
+        assert_eq!(ctx.budget.remaining(Spend::SentMsat, &ctx.config), Some(0));
+        assert_eq!(ctx.budget.remaining(Spend::OpenSat, &ctx.config), Some(500_000));
+        for name in ["spaz_sent_msat_total", "spaz_channels_opened_total", "spaz_channels_closed_total"] {
+            assert!(counted(&ctx, name), "{} was not counted", name);
+        }
+    }
+
+    #[test]
+    fn in_flight_reservations_hold_the_budget() {
+        let ctx = context(false);
+        let first = ctx.reserve(&[(Spend::SentMsat, 6_000)], "02aa");
+        assert!(first.is_some());
+        assert!(ctx.reserve(&[(Spend::SentMsat, 6_000)], "02bb").is_none());
+        // A failed call drops its reservation, which frees the budget again
+        drop(first);
+        assert!(ctx.reserve(&[(Spend::SentMsat, 6_000)], "02bb").is_some());
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use cln_rpc::model;
+use cln_rpc::primitives::PublicKey;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore};
+use std::convert::TryInto;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::dry_run::DryRunLog;
+use crate::metrics::Metrics;
+use crate::{random_in_range, Amount, Channel, Config, Node, Peer, Sha256};
+
+/// What `keysend_node` returns in dry-run mode: a complete payment of
+/// `amount` with no fees and an all-zero hash and preimage
+pub fn dry_run_keysend(pubkey: PublicKey, amount: Amount) -> Result<model::KeysendResponse, Error> {
+    Ok(model::KeysendResponse {
+        payment_preimage: cln_rpc::primitives::Secret::try_from(vec![0u8; 32])?,
+        destination: Some(pubkey),
+        payment_hash: Sha256::all_zeros(),
+        created_at: 0.0,
+        parts: 0,
+        amount_msat: cln_rpc::primitives::Amount::from_msat(amount.msat()),
+        amount_sent_msat: cln_rpc::primitives::Amount::from_msat(amount.msat()),
+        warning_partial_completion: None,
+        status: model::KeysendStatus::COMPLETE,
+    })
+}
+
+/// Runs `future` on a task of its own, returning a future for its output.
+/// Trait futures aren't `Sync`, which RPC handlers need, so handlers call
+/// backends through this.  It spawns right away rather than being an
+/// `async fn`, which would hold `future` itself across the await.
+pub fn spawn_backend<T: Send + 'static>(future: impl Future<Output = T> + Send + 'static) -> impl Future<Output = Result<T, Error>> {
+    let handle = tokio::task::spawn(future);
+    async move { handle.await.map_err(|e| anyhow!("Backend task failed: {}", e)) }
+}
+
+/// The node spaz acts on.  Actions only ever talk to lightning through this,
+/// so they can run against any implementation, e.g. `ClnClient`.
+#[async_trait]
+pub trait LightningBackend: Send + Sync {
+    /// A backend for one run with `config`, recording mutating calls in
+    /// `dry_run` instead of making them when it is set
+    fn for_run(&self, config: &Config, dry_run: Option<Arc<DryRunLog>>) -> Arc<dyn LightningBackend>;
+
+    /// The network the node is running on, in CLN's terms, e.g. `regtest` or `bitcoin` for mainnet
+    async fn network(&self) -> Result<String, Error>;
+
+    /// Our funded channels
+    async fn list_channels(&self) -> Result<Vec<Channel>, Error>;
+
+    async fn list_peers(&self) -> Result<Vec<Peer>, Error>;
+
+    /// Every node in the gossip graph
+    async fn list_nodes(&self) -> Result<Vec<Node>, Error>;
+
+    async fn keysend_node(&self, pubkey: PublicKey, amount: Amount) -> Result<model::KeysendResponse, Error>;
+
+    /// Finds a route to `pubkey` and sends `amount_msat` along it for
+    /// `payment_hash`, which the destination is not expected to know.
+    /// Returns the fees along the route, in msat.
+    async fn route_and_sendpay(&self, pubkey: PublicKey, amount_msat: u64, payment_hash: Sha256) -> Result<u64, Error>;
+
+    async fn set_channel_fee(&self, short_channel_id: &str, fee_ppm: u32, fee_base_msat: u64) -> Result<(), Error>;
+
+    /// Connects to `node` if it has an IPv4 address and funds a channel of
+    /// `size` sat, returning the funding txid
+    async fn open_channel_to_node(&self, node: Node, size: u64) -> Result<String, Error>;
+
+    /// Closes the channel, returning the closing txid if there is one
+    async fn close_channel(&self, short_channel_id: &str) -> Result<Option<String>, Error>;
+
+    async fn disconnect_peer(&self, pubkey: PublicKey) -> Result<(), Error>;
+
+    async fn ping_peer(&self, pubkey: PublicKey, len: u32, pongbytes: u32) -> Result<(), Error>;
+
+    /// Where calls that change anything are recorded instead of made, in dry-run mode
+    fn dry_run_log(&self) -> Option<&DryRunLog> {
+        None
+    }
+
+    /// Whether calls that change anything are being recorded instead of made
+    fn dry_run(&self) -> bool {
+        self.dry_run_log().is_some()
+    }
+
+    /// Records `method` if in dry-run mode.  Returns true if the call should be skipped.
+    fn skip_for_dry_run(&self, method: &str, params: serde_json::Value) -> bool {
+        match self.dry_run_log() {
+            Some(log) => {
+                log.record(method, params);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Where RPC latency is recorded, if anywhere
+    fn metrics(&self) -> Option<Arc<Metrics>> {
+        None
+    }
+
+    /// Checks the node's network against the config, logging why if it's
+    /// refused.  Dry runs never change anything, so they are always allowed.
+    async fn check_network(&self, config: &Config) -> Result<(), Error> {
+        if config.dry_run {
+            return Ok(())
+        }
+        let network = self.network().await?;
+        config.check_network(&network).map_err(|e| {
+            log::warn!("{}", e);
+            e
+        })
+    }
+
+    /// Short channel ids marked protected on the node itself.  Backends
+    /// without a datastore have none.
+    async fn protected_channels(&self) -> Result<Vec<String>, Error> {
+        Ok(vec![])
+    }
+
+    /// Stores `value` under a new datastore key
+    async fn datastore(&self, _key: Vec<String>, _value: String) -> Result<(), Error> {
+        Err(anyhow!("This backend has no datastore"))
+    }
+
+    async fn list_datastore(&self, _key: &[&str]) -> Result<Vec<model::ListdatastoreDatastore>, Error> {
+        Err(anyhow!("This backend has no datastore"))
+    }
+
+    /// Sends `amount` msat to `pubkey` for a random payment hash, so the
+    /// payment fails at the destination after exercising the route.
+    /// Returns the fees along the route, in msat.
+    async fn poke_node(&self, pubkey: PublicKey, amount: u64, rng: &mut StdRng) -> Result<u64, Error> {
+        log::info!("Poking node {:?}, {:?}", pubkey, amount);
+        let mut random_bytes = [0u8; 32];
+        rng.fill_bytes(&mut random_bytes);
+        let payment_hash = Sha256::from_slice(&random_bytes)?;
+        self.route_and_sendpay(pubkey, amount, payment_hash).await
+    }
+
+    /// Sets a random fee on the channel, returning the (ppm, base msat) it picked
+    async fn randomize_fee(&self, short_channel_id: &str, ppm_range: (u64, u64), base_range_msat: (u64, u64), rng: &mut StdRng) -> Result<(u32, u64), Error> {
+        let random_ppm: u32 = random_in_range(rng, ppm_range.0, ppm_range.1).try_into()?;
+        let random_base: u64 = random_in_range(rng, base_range_msat.0, base_range_msat.1);
+        self.set_channel_fee(short_channel_id, random_ppm, random_base).await?;
+        Ok((random_ppm, random_base))
+    }
+
+    /// Pings the peer with random ping and pong lengths
+    async fn random_ping_peer(&self, pubkey: PublicKey, rng: &mut StdRng) -> Result<(), Error> {
+        let ping_len: u32 = rng.gen();
+        let pong_len: u32 = rng.gen();
+        self.ping_peer(pubkey, ping_len, pong_len).await?;
+        log::info!("Pinged peer (Ping Length: {}, Pong Length: {})", ping_len, pong_len);
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+use spaz::actions::Registry;
+use std::path::PathBuf;
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    spaz::daemon::init_logging();
+    let path = std::env::args_os().nth(1)
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("Usage: spaz-daemon <config file>"))?;
+    spaz::daemon::run_file(&path, Registry::builtin()).await
+}
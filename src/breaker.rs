@@ -0,0 +1,163 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{Config, MAX_SECS};
+
+/// Failure tracking for actions and for (action, target) pairs.
+///
+/// After `breaker_threshold` consecutive failures a key is suspended for
+/// `breaker_cooldown_secs`.  Once the cooldown is over the key gets one more
+/// try; every further failure doubles the cooldown (up to
+/// `breaker_max_cooldown_secs`) and a single success resets it.
+#[derive(Default)]
+pub struct Breakers {
+    inner: Mutex<BTreeMap<String, BreakerState>>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    suspended_until: Option<Instant>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suspension {
+    pub key: String,
+    pub consecutive_failures: u32,
+    pub remaining_secs: u64,
+}
+
+impl Breakers {
+    pub fn key(action: &str, target: Option<&str>) -> String {
+        match target {
+            Some(t) => format!("{}:{}", action, t),
+            None => action.to_string(),
+        }
+    }
+
+    pub fn is_suspended(&self, key: &str) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.get(key).and_then(|s| s.suspended_until) {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    pub fn record_success(&self, key: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(state) = inner.remove(key) {
+            if state.consecutive_failures > 0 {
+                log::debug!("{} succeeded after {} failures", key, state.consecutive_failures);
+            }
+        }
+    }
+
+    pub fn record_failure(&self, key: &str, config: &Config) {
+        if config.breaker_threshold == 0 {
+            return
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let state = inner.entry(key.to_string()).or_default();
+        state.consecutive_failures += 1;
+        if let Some(cooldown) = cooldown(state.consecutive_failures, config) {
+            log::warn!("Suspending {} for {:?} after {} consecutive failures", key, cooldown, state.consecutive_failures);
+            state.suspended_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    pub fn suspended(&self) -> Vec<Suspension> {
+        let now = Instant::now();
+        let inner = self.inner.lock().unwrap();
+        inner.iter()
+            .filter_map(|(key, state)| match state.suspended_until {
+                Some(until) if until > now => Some(Suspension {
+                    key: key.clone(),
+                    consecutive_failures: state.consecutive_failures,
+                    remaining_secs: (until - now).as_secs(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// How long to suspend a key after `consecutive_failures`, if at all.
+/// Cooldowns are capped at `MAX_SECS` as well as at
+/// `breaker_max_cooldown_secs`, so a config that skipped validation can't
+/// make this panic.
+fn cooldown(consecutive_failures: u32, config: &Config) -> Option<Duration> {
+    if config.breaker_threshold == 0 || consecutive_failures < config.breaker_threshold {
+        return None
+    }
+    let doublings = (consecutive_failures - config.breaker_threshold).min(32);
+    let secs = (config.breaker_cooldown_secs * 2f64.powi(doublings as i32))
+        .min(config.breaker_max_cooldown_secs)
+        .clamp(0.0, MAX_SECS);
+    Some(Duration::try_from_secs_f64(secs).unwrap_or(Duration::from_secs_f64(MAX_SECS)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config { breaker_threshold: 3, breaker_cooldown_secs: 60.0, breaker_max_cooldown_secs: 300.0, ..Config::default() }
+    }
+
+    #[test]
+    fn cooldown_doubles_up_to_the_cap() {
+        let config = config();
+        assert_eq!(cooldown(1, &config), None);
+        assert_eq!(cooldown(2, &config), None);
+        assert_eq!(cooldown(3, &config), Some(Duration::from_secs(60)));
+        assert_eq!(cooldown(4, &config), Some(Duration::from_secs(120)));
+        assert_eq!(cooldown(5, &config), Some(Duration::from_secs(240)));
+        assert_eq!(cooldown(6, &config), Some(Duration::from_secs(300)));
+        assert_eq!(cooldown(u32::MAX, &config), Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn unchecked_cooldowns_dont_panic() {
+        let max = Some(Duration::from_secs_f64(MAX_SECS));
+        let inf = Config { breaker_max_cooldown_secs: f64::INFINITY, ..config() };
+        assert_eq!(cooldown(100, &inf), max);
+        let nan = Config { breaker_cooldown_secs: f64::NAN, breaker_max_cooldown_secs: f64::NAN, ..config() };
+        assert_eq!(cooldown(3, &nan), max);
+    }
+
+    #[test]
+    fn threshold_suspends_and_success_resets() {
+        let config = config();
+        let breakers = Breakers::default();
+        let key = Breakers::key("keysend", Some("02aa"));
+        breakers.record_failure(&key, &config);
+        breakers.record_failure(&key, &config);
+        assert!(!breakers.is_suspended(&key));
+        breakers.record_failure(&key, &config);
+        assert!(breakers.is_suspended(&key));
+        assert!(!breakers.is_suspended("keysend"));
+        let suspended = breakers.suspended();
+        assert_eq!(suspended.len(), 1);
+        assert_eq!(suspended[0].consecutive_failures, 3);
+        assert!(suspended[0].remaining_secs <= 60);
+
+        breakers.record_success(&key);
+        assert!(!breakers.is_suspended(&key));
+        assert!(breakers.suspended().is_empty());
+        // The count starts over, so it takes a full threshold of failures again
+        breakers.record_failure(&key, &config);
+        assert!(!breakers.is_suspended(&key));
+    }
+
+    #[test]
+    fn zero_threshold_disables_breakers() {
+        let config = Config { breaker_threshold: 0, ..config() };
+        let breakers = Breakers::default();
+        for _ in 0..10 {
+            breakers.record_failure("fee", &config);
+        }
+        assert!(!breakers.is_suspended("fee"));
+    }
+}
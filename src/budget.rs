@@ -0,0 +1,305 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::Config;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The most a payment of `amount_msat` may pay in routing fees: 0.5%, but
+/// at least 5 sat.  These are CLN's keysend defaults, and LND keysends are
+/// sent with the same limit.
+pub fn max_fee_msat(amount_msat: u64) -> u64 {
+    (amount_msat / 200).max(5000)
+}
+
+/// What a budget limits
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Spend {
+    /// msat sent by keysends and pokes
+    SentMsat,
+    /// msat paid in routing fees
+    FeesMsat,
+    /// sat committed to channel opens
+    OpenSat,
+}
+
+impl Spend {
+    pub const ALL: [Spend; 3] = [Spend::SentMsat, Spend::FeesMsat, Spend::OpenSat];
+
+    /// (per hour, per day) limits, where 0 means unlimited
+    fn limits(&self, config: &Config) -> (u64, u64) {
+        match self {
+            Spend::SentMsat => (config.budget_sent_msat_per_hour, config.budget_sent_msat_per_day),
+            Spend::FeesMsat => (config.budget_fees_msat_per_hour, config.budget_fees_msat_per_day),
+            Spend::OpenSat => (config.budget_open_sat_per_hour, config.budget_open_sat_per_day),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetReport {
+    pub spend: Spend,
+    pub spent_last_hour: u64,
+    pub spent_last_day: u64,
+    /// `None` when unlimited
+    pub remaining_hour: Option<u64>,
+    pub remaining_day: Option<u64>,
+}
+
+/// Rolling hourly and daily spend tracking.  Calls that spend reserve
+/// their worst case before acting, which counts against the budgets at
+/// once, and settle the reservation with what was actually spent afterwards.
+#[derive(Default)]
+pub struct Budget {
+    ledger: Mutex<Ledger>,
+}
+
+#[derive(Default)]
+struct Ledger {
+    entries: VecDeque<(Instant, Spend, u64)>,
+    /// Amounts held by reservations that haven't been settled, by reservation id
+    held: BTreeMap<u64, Vec<(Spend, u64)>>,
+    next_id: u64,
+}
+
+impl Ledger {
+    fn spent(&self, spend: Spend, window: Duration, now: Instant) -> u64 {
+        let settled: u64 = self.entries.iter()
+            .filter(|(at, s, _)| *s == spend && now.saturating_duration_since(*at) < window)
+            .map(|(_, _, amount)| amount)
+            .sum();
+        let held: u64 = self.held.values()
+            .flatten()
+            .filter(|(s, _)| *s == spend)
+            .map(|(_, amount)| amount)
+            .sum();
+        settled + held
+    }
+
+    fn remaining(&self, spend: Spend, config: &Config, now: Instant) -> Option<u64> {
+        let (hour, day) = spend.limits(config);
+        let remaining_hour = (hour > 0).then(|| hour.saturating_sub(self.spent(spend, HOUR, now)));
+        let remaining_day = (day > 0).then(|| day.saturating_sub(self.spent(spend, DAY, now)));
+        match (remaining_hour, remaining_day) {
+            (Some(h), Some(d)) => Some(h.min(d)),
+            (h, d) => h.or(d),
+        }
+    }
+
+    fn allows(&self, spend: Spend, amount: u64, config: &Config, now: Instant) -> bool {
+        match self.remaining(spend, config, now) {
+            Some(remaining) if remaining == 0 || amount > remaining => {
+                log::info!("Skipping, {:?} budget exhausted ({} remaining, {} requested)", spend, remaining, amount);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn record(&mut self, spend: Spend, amount: u64, now: Instant) {
+        if amount == 0 {
+            return
+        }
+        while self.entries.front().map(|(at, _, _)| now.saturating_duration_since(*at) >= DAY).unwrap_or(false) {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((now, spend, amount));
+    }
+
+    fn hold(&mut self, amounts: &[(Spend, u64)]) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.held.insert(id, amounts.to_vec());
+        id
+    }
+}
+
+/// Amounts held against a `Budget` while the call that spends them is in
+/// flight.  Settle it with what was actually spent; dropping it releases
+/// the hold without charging anything.
+pub struct Reservation {
+    budget: Arc<Budget>,
+    id: u64,
+}
+
+impl Reservation {
+    /// Replaces the hold with what was actually spent
+    pub fn settle(self, spent: &[(Spend, u64)]) {
+        let now = Instant::now();
+        let mut ledger = self.budget.ledger.lock().unwrap();
+        ledger.held.remove(&self.id);
+        for (spend, amount) in spent {
+            ledger.record(*spend, *amount, now);
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.ledger.lock().unwrap().held.remove(&self.id);
+    }
+}
+
+impl Budget {
+    /// Amount still available in the tighter of the two windows, or `None` if unlimited
+    pub fn remaining(&self, spend: Spend, config: &Config) -> Option<u64> {
+        self.ledger.lock().unwrap().remaining(spend, config, Instant::now())
+    }
+
+    /// Whether `amount` more can be spent without going over budget.  An
+    /// exhausted budget allows nothing, not even an amount of 0.
+    pub fn allows(&self, spend: Spend, amount: u64, config: &Config) -> bool {
+        self.ledger.lock().unwrap().allows(spend, amount, config, Instant::now())
+    }
+
+    /// Holds all of `amounts` if every one of them is within budget, or
+    /// none of them, returning the first spend that isn't.  Checking and
+    /// holding under one lock keeps concurrent runs from each passing the
+    /// check and overshooting a budget together.
+    pub fn reserve(self: &Arc<Self>, amounts: &[(Spend, u64)], config: &Config) -> Result<Reservation, Spend> {
+        let now = Instant::now();
+        let mut ledger = self.ledger.lock().unwrap();
+        if let Some((spend, _)) = amounts.iter().find(|(spend, amount)| !ledger.allows(*spend, *amount, config, now)) {
+            return Err(*spend)
+        }
+        let id = ledger.hold(amounts);
+        Ok(Reservation { budget: self.clone(), id })
+    }
+
+    /// Holds `amounts` without checking them against the budgets, for
+    /// forced runs that must still be charged for what they spend
+    pub fn hold(self: &Arc<Self>, amounts: &[(Spend, u64)]) -> Reservation {
+        let id = self.ledger.lock().unwrap().hold(amounts);
+        Reservation { budget: self.clone(), id }
+    }
+
+    pub fn record(&self, spend: Spend, amount: u64) {
+        self.ledger.lock().unwrap().record(spend, amount, Instant::now());
+    }
+
+    pub fn report(&self, config: &Config) -> Vec<BudgetReport> {
+        Spend::ALL.into_iter()
+            .map(|spend| {
+                let (hour, day) = spend.limits(config);
+                let ledger = self.ledger.lock().unwrap();
+                let now = Instant::now();
+                let spent_last_hour = ledger.spent(spend, HOUR, now);
+                let spent_last_day = ledger.spent(spend, DAY, now);
+                BudgetReport {
+                    spend,
+                    spent_last_hour,
+                    spent_last_day,
+                    remaining_hour: (hour > 0).then(|| hour.saturating_sub(spent_last_hour)),
+                    remaining_day: (day > 0).then(|| day.saturating_sub(spent_last_day)),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(fees_per_hour: u64, fees_per_day: u64) -> Config {
+        Config { budget_fees_msat_per_hour: fees_per_hour, budget_fees_msat_per_day: fees_per_day, ..Config::default() }
+    }
+
+    #[test]
+    fn unlimited_allows_anything() {
+        let budget = Budget::default();
+        budget.record(Spend::FeesMsat, 1_000_000);
+        assert!(budget.allows(Spend::FeesMsat, u64::MAX, &config(0, 0)));
+        assert_eq!(budget.remaining(Spend::FeesMsat, &config(0, 0)), None);
+    }
+
+    #[test]
+    fn exhausted_budget_refuses_zero_amount() {
+        let budget = Budget::default();
+        let config = config(10_000, 0);
+        assert!(budget.allows(Spend::FeesMsat, 0, &config));
+        budget.record(Spend::FeesMsat, 10_000);
+        assert_eq!(budget.remaining(Spend::FeesMsat, &config), Some(0));
+        assert!(!budget.allows(Spend::FeesMsat, 0, &config));
+        assert!(!budget.allows(Spend::FeesMsat, 1, &config));
+    }
+
+    #[test]
+    fn amount_over_remaining_is_refused() {
+        let budget = Budget::default();
+        let config = config(10_000, 0);
+        budget.record(Spend::FeesMsat, 4_000);
+        assert!(budget.allows(Spend::FeesMsat, 6_000, &config));
+        assert!(!budget.allows(Spend::FeesMsat, 6_001, &config));
+        // Other kinds of spend have budgets of their own
+        assert!(budget.allows(Spend::SentMsat, 1_000_000, &config));
+    }
+
+    #[test]
+    fn tighter_window_wins() {
+        let budget = Budget::default();
+        budget.record(Spend::FeesMsat, 8_000);
+        assert_eq!(budget.remaining(Spend::FeesMsat, &config(10_000, 50_000)), Some(2_000));
+        assert_eq!(budget.remaining(Spend::FeesMsat, &config(100_000, 9_000)), Some(1_000));
+    }
+
+    #[test]
+    fn windows_reset() {
+        let budget = Budget::default();
+        let config = config(10_000, 15_000);
+        budget.record(Spend::FeesMsat, 10_000);
+        let ledger = budget.ledger.lock().unwrap();
+        let now = Instant::now();
+        assert!(!ledger.allows(Spend::FeesMsat, 0, &config, now));
+
+        // The hourly window has rolled over, but the daily one still counts the spend
+        let later = now + HOUR;
+        assert_eq!(ledger.remaining(Spend::FeesMsat, &config, later), Some(5_000));
+        assert!(ledger.allows(Spend::FeesMsat, 5_000, &config, later));
+        assert!(!ledger.allows(Spend::FeesMsat, 5_001, &config, later));
+
+        let tomorrow = now + DAY;
+        assert_eq!(ledger.remaining(Spend::FeesMsat, &config, tomorrow), Some(10_000));
+    }
+
+    #[test]
+    fn reservations_count_until_settled_or_released() {
+        let budget = Arc::new(Budget::default());
+        let config = Config { budget_sent_msat_per_hour: 10_000, ..config(10_000, 0) };
+        let first = budget.reserve(&[(Spend::SentMsat, 6_000), (Spend::FeesMsat, 5_000)], &config).ok().unwrap();
+        // A concurrent run can't also pass the check for what the first one holds
+        assert_eq!(budget.reserve(&[(Spend::SentMsat, 6_000)], &config).err(), Some(Spend::SentMsat));
+        // All or nothing: an exhausted fee budget holds back the sent amount too
+        assert_eq!(budget.reserve(&[(Spend::SentMsat, 1_000), (Spend::FeesMsat, 6_000)], &config).err(), Some(Spend::FeesMsat));
+        assert_eq!(budget.remaining(Spend::SentMsat, &config), Some(4_000));
+
+        first.settle(&[(Spend::SentMsat, 6_000), (Spend::FeesMsat, 100)]);
+        assert_eq!(budget.remaining(Spend::SentMsat, &config), Some(4_000));
+        assert_eq!(budget.remaining(Spend::FeesMsat, &config), Some(9_900));
+
+        let second = budget.reserve(&[(Spend::SentMsat, 4_000)], &config).ok().unwrap();
+        assert_eq!(budget.remaining(Spend::SentMsat, &config), Some(0));
+        drop(second);
+        assert_eq!(budget.remaining(Spend::SentMsat, &config), Some(4_000));
+    }
+
+    #[test]
+    fn forced_holds_skip_the_check() {
+        let budget = Arc::new(Budget::default());
+        let config = config(1_000, 0);
+        budget.hold(&[(Spend::FeesMsat, 5_000)]).settle(&[(Spend::FeesMsat, 2_000)]);
+        assert_eq!(budget.remaining(Spend::FeesMsat, &config), Some(0));
+        assert!(budget.reserve(&[(Spend::FeesMsat, 0)], &config).is_err());
+    }
+
+    #[test]
+    fn max_fee_is_half_a_percent_with_a_floor() {
+        assert_eq!(max_fee_msat(0), 5_000);
+        assert_eq!(max_fee_msat(1_000_000), 5_000);
+        assert_eq!(max_fee_msat(10_000_000), 50_000);
+    }
+}
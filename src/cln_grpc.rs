@@ -0,0 +1,643 @@
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use cln_rpc::model;
+use cln_rpc::primitives::PublicKey;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+
+use crate::backend::dry_run_keysend;
+use crate::dry_run::DryRunLog;
+use crate::grpc;
+use crate::metrics::Metrics;
+use crate::{Amount, Channel, Config, LightningBackend, ListnodesNodesAddress, ListnodesNodesAddressType, MyCustomError, Node, Peer, Sha256, PROTECTED_DATASTORE_KEY};
+
+const GETINFO: &str = "/cln.Node/Getinfo";
+const LIST_PEERS: &str = "/cln.Node/ListPeers";
+const LIST_FUNDS: &str = "/cln.Node/ListFunds";
+const LIST_NODES: &str = "/cln.Node/ListNodes";
+const KEYSEND: &str = "/cln.Node/KeySend";
+const GET_ROUTE: &str = "/cln.Node/GetRoute";
+const SEND_PAY: &str = "/cln.Node/SendPay";
+const SET_CHANNEL: &str = "/cln.Node/SetChannel";
+const CONNECT_PEER: &str = "/cln.Node/ConnectPeer";
+const FUND_CHANNEL: &str = "/cln.Node/FundChannel";
+const CLOSE: &str = "/cln.Node/Close";
+const DISCONNECT: &str = "/cln.Node/Disconnect";
+const PING: &str = "/cln.Node/Ping";
+const DATASTORE: &str = "/cln.Node/Datastore";
+const LIST_DATASTORE: &str = "/cln.Node/ListDatastore";
+
+/// cln-grpc's server certificate is issued for this name, whatever the host
+const SERVER_NAME: &str = "cln";
+
+/// Just the parts of cln-grpc's `node.proto` and `primitives.proto` that spaz
+/// uses.  Field numbers are the stable ones cln-grpc has kept since v23.02;
+/// fields spaz doesn't read are left out and skipped when decoding.
+pub mod proto {
+    pub const DATASTORE_MUST_CREATE: i32 = 0;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Amount {
+        #[prost(uint64, tag = "1")]
+        pub msat: u64,
+    }
+
+    /// A oneof of `amount` and `all` upstream; spaz only ever sends an amount
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AmountOrAll {
+        #[prost(message, optional, tag = "1")]
+        pub amount: Option<Amount>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetinfoRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetinfoResponse {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(string, tag = "12")]
+        pub network: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListpeersRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListpeersResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub peers: Vec<ListpeersPeers>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListpeersPeers {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(bool, tag = "2")]
+        pub connected: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListfundsRequest {
+        #[prost(bool, optional, tag = "1")]
+        pub spent: Option<bool>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListfundsResponse {
+        #[prost(message, repeated, tag = "2")]
+        pub channels: Vec<ListfundsChannels>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListfundsChannels {
+        #[prost(bytes = "vec", tag = "1")]
+        pub peer_id: Vec<u8>,
+        #[prost(message, optional, tag = "2")]
+        pub our_amount_msat: Option<Amount>,
+        #[prost(message, optional, tag = "3")]
+        pub amount_msat: Option<Amount>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub funding_txid: Vec<u8>,
+        #[prost(uint32, tag = "5")]
+        pub funding_output: u32,
+        #[prost(bool, tag = "6")]
+        pub connected: bool,
+        #[prost(string, optional, tag = "8")]
+        pub short_channel_id: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListnodesRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListnodesResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub nodes: Vec<ListnodesNodes>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListnodesNodes {
+        #[prost(bytes = "vec", tag = "1")]
+        pub nodeid: Vec<u8>,
+        #[prost(uint32, optional, tag = "2")]
+        pub last_timestamp: Option<u32>,
+        #[prost(string, optional, tag = "3")]
+        pub alias: Option<String>,
+        #[prost(bytes = "vec", optional, tag = "4")]
+        pub color: Option<Vec<u8>>,
+        #[prost(bytes = "vec", optional, tag = "5")]
+        pub features: Option<Vec<u8>>,
+        #[prost(message, repeated, tag = "6")]
+        pub addresses: Vec<ListnodesNodesAddresses>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListnodesNodesAddresses {
+        #[prost(int32, tag = "1")]
+        pub item_type: i32,
+        #[prost(uint32, tag = "2")]
+        pub port: u32,
+        #[prost(string, optional, tag = "3")]
+        pub address: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeysendRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub destination: Vec<u8>,
+        #[prost(message, optional, tag = "10")]
+        pub amount_msat: Option<Amount>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeysendResponse {
+        #[prost(bytes = "vec", tag = "1")]
+        pub payment_preimage: Vec<u8>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub destination: Option<Vec<u8>>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub payment_hash: Vec<u8>,
+        #[prost(double, tag = "4")]
+        pub created_at: f64,
+        #[prost(uint32, tag = "5")]
+        pub parts: u32,
+        #[prost(message, optional, tag = "6")]
+        pub amount_msat: Option<Amount>,
+        #[prost(message, optional, tag = "7")]
+        pub amount_sent_msat: Option<Amount>,
+        #[prost(string, optional, tag = "8")]
+        pub warning_partial_completion: Option<String>,
+        #[prost(int32, tag = "9")]
+        pub status: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetrouteRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(uint64, tag = "3")]
+        pub riskfactor: u64,
+        #[prost(message, optional, tag = "9")]
+        pub amount_msat: Option<Amount>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetrouteResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub route: Vec<GetrouteRoute>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetrouteRoute {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(string, tag = "2")]
+        pub channel: String,
+        #[prost(message, optional, tag = "4")]
+        pub amount_msat: Option<Amount>,
+        #[prost(uint32, tag = "5")]
+        pub delay: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SendpayRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub route: Vec<SendpayRoute>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub payment_hash: Vec<u8>,
+        #[prost(bytes = "vec", optional, tag = "6")]
+        pub payment_secret: Option<Vec<u8>>,
+        #[prost(message, optional, tag = "10")]
+        pub amount_msat: Option<Amount>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SendpayRoute {
+        #[prost(bytes = "vec", tag = "2")]
+        pub id: Vec<u8>,
+        #[prost(uint32, tag = "3")]
+        pub delay: u32,
+        #[prost(string, tag = "4")]
+        pub channel: String,
+        #[prost(message, optional, tag = "5")]
+        pub amount_msat: Option<Amount>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SendpayResponse {
+        #[prost(string, tag = "3")]
+        pub status: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SetchannelRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(message, optional, tag = "2")]
+        pub feebase: Option<Amount>,
+        #[prost(uint32, optional, tag = "3")]
+        pub feeppm: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SetchannelResponse {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ConnectRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, optional, tag = "2")]
+        pub host: Option<String>,
+        #[prost(uint32, optional, tag = "3")]
+        pub port: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ConnectResponse {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FundchannelRequest {
+        #[prost(message, optional, tag = "1")]
+        pub amount: Option<AmountOrAll>,
+        #[prost(bytes = "vec", tag = "9")]
+        pub id: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FundchannelResponse {
+        #[prost(bytes = "vec", tag = "2")]
+        pub txid: Vec<u8>,
+        #[prost(uint32, tag = "3")]
+        pub outnum: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CloseRequest {
+        #[prost(string, tag = "1")]
+        pub id: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CloseResponse {
+        #[prost(bytes = "vec", optional, tag = "3")]
+        pub txid: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DisconnectRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(bool, optional, tag = "2")]
+        pub force: Option<bool>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DisconnectResponse {}
+
+    // prost generates a `len()` getter for the optional field
+    #[allow(clippy::len_without_is_empty)]
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PingRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub id: Vec<u8>,
+        #[prost(uint32, optional, tag = "2")]
+        pub len: Option<u32>,
+        #[prost(uint32, optional, tag = "3")]
+        pub pongbytes: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PingResponse {
+        #[prost(uint32, tag = "1")]
+        pub totlen: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DatastoreRequest {
+        #[prost(int32, optional, tag = "3")]
+        pub mode: Option<i32>,
+        #[prost(string, repeated, tag = "5")]
+        pub key: Vec<String>,
+        #[prost(string, optional, tag = "6")]
+        pub string: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DatastoreResponse {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListdatastoreRequest {
+        #[prost(string, repeated, tag = "2")]
+        pub key: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListdatastoreResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub datastore: Vec<ListdatastoreDatastore>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListdatastoreDatastore {
+        #[prost(string, repeated, tag = "1")]
+        pub key: Vec<String>,
+        #[prost(uint64, optional, tag = "2")]
+        pub generation: Option<u64>,
+        #[prost(bytes = "vec", optional, tag = "3")]
+        pub hex: Option<Vec<u8>>,
+        #[prost(string, optional, tag = "4")]
+        pub string: Option<String>,
+    }
+}
+
+/// How to reach a CLN node's grpc interface, from the `[cln_grpc]` table of
+/// the daemon's config file.  The certificates are the ones cln-grpc writes
+/// to lightningd's network directory on first start.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClnGrpcConfig {
+    /// e.g. `https://cln.internal:9736`, the node's `grpc-port`
+    pub address: String,
+    /// `ca.pem`
+    pub ca_cert: String,
+    /// `client.pem`
+    pub client_cert: String,
+    /// `client-key.pem`
+    pub client_key: String,
+}
+
+/// A CLN node over cln-grpc, authenticated with a client certificate, for
+/// when spaz can't reach the node's RPC socket
+#[derive(Clone)]
+pub struct ClnGrpcClient {
+    channel: tonic::transport::Channel,
+    /// When set, mutating calls are recorded here instead of being sent to lightningd
+    pub dry_run: Option<Arc<DryRunLog>>,
+    /// When set, the latency of every call is recorded here
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl ClnGrpcClient {
+    pub async fn connect(config: &ClnGrpcConfig) -> Result<ClnGrpcClient, Error> {
+        let ca = grpc::read_file(&config.ca_cert, "ca_cert")?;
+        let cert = grpc::read_file(&config.client_cert, "client_cert")?;
+        let key = grpc::read_file(&config.client_key, "client_key")?;
+        let tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca))
+            .identity(Identity::from_pem(cert, key))
+            .domain_name(SERVER_NAME);
+        let channel = Endpoint::from_shared(config.address.clone())?
+            .tls_config(tls)?
+            .connect()
+            .await
+            .map_err(|e| anyhow!("Unable to connect to cln-grpc at {}: {}", config.address, e))?;
+        log::info!("Connected to cln-grpc at {}", config.address);
+        Ok(ClnGrpcClient { channel, dry_run: None, metrics: None })
+    }
+
+    async fn unary<Req, Resp>(&self, path: &'static str, message: Req) -> Result<Resp, Error>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        grpc::unary(&self.channel, path, tonic::Request::new(message), self.metrics.as_deref()).await
+    }
+}
+
+fn amount(msat: u64) -> Option<proto::Amount> {
+    Some(proto::Amount { msat })
+}
+
+fn msat(amount: Option<proto::Amount>) -> u64 {
+    amount.map(|a| a.msat).unwrap_or_default()
+}
+
+#[async_trait]
+impl LightningBackend for ClnGrpcClient {
+    fn for_run(&self, _config: &Config, dry_run: Option<Arc<DryRunLog>>) -> Arc<dyn LightningBackend> {
+        Arc::new(ClnGrpcClient { dry_run, ..self.clone() })
+    }
+
+    async fn network(&self) -> Result<String, Error> {
+        let res: proto::GetinfoResponse = self.unary(GETINFO, proto::GetinfoRequest {}).await?;
+        Ok(res.network)
+    }
+
+    fn dry_run_log(&self) -> Option<&DryRunLog> {
+        self.dry_run.as_deref()
+    }
+
+    fn metrics(&self) -> Option<Arc<Metrics>> {
+        self.metrics.clone()
+    }
+
+    async fn list_channels(&self) -> Result<Vec<Channel>, Error> {
+        let res: proto::ListfundsResponse = self.unary(LIST_FUNDS, proto::ListfundsRequest { spent: Some(false) }).await?;
+        Ok(res.channels.into_iter()
+            .map(|c| Channel {
+                peer_id: hex::encode(c.peer_id),
+                connected: c.connected,
+                our_amount_msat: Amount::from_msat(msat(c.our_amount_msat)),
+                amount_msat: Amount::from_msat(msat(c.amount_msat)),
+                funding_txid: hex::encode(c.funding_txid),
+                funding_output: c.funding_output,
+                short_channel_id: c.short_channel_id,
+            })
+            .collect())
+    }
+
+    async fn list_peers(&self) -> Result<Vec<Peer>, Error> {
+        let res: proto::ListpeersResponse = self.unary(LIST_PEERS, proto::ListpeersRequest {}).await?;
+        res.peers.into_iter()
+            .map(|p| Ok(Peer { id: PublicKey::from_slice(&p.id)?, connected: p.connected }))
+            .collect()
+    }
+
+    async fn list_nodes(&self) -> Result<Vec<Node>, Error> {
+        let res: proto::ListnodesResponse = self.unary(LIST_NODES, proto::ListnodesRequest {}).await?;
+        res.nodes.into_iter()
+            .map(|n| {
+                let addresses = n.addresses.into_iter()
+                    .map(|a| Ok(ListnodesNodesAddress {
+                        item_type: ListnodesNodesAddressType::try_from(a.item_type)?,
+                        port: u16::try_from(a.port)?,
+                        address: a.address,
+                    }))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Ok(Node {
+                    nodeid: PublicKey::from_slice(&n.nodeid)?,
+                    last_timestamp: n.last_timestamp,
+                    alias: n.alias,
+                    color: n.color.map(hex::encode),
+                    features: n.features.map(hex::encode),
+                    // Nodes we only know from channel announcements have no node announcement, hence no addresses
+                    addresses: n.last_timestamp.map(|_| addresses),
+                })
+            })
+            .collect()
+    }
+
+    async fn list_datastore(&self, key: &[&str]) -> Result<Vec<model::ListdatastoreDatastore>, Error> {
+        let req = proto::ListdatastoreRequest { key: key.iter().map(|k| k.to_string()).collect() };
+        let res: proto::ListdatastoreResponse = self.unary(LIST_DATASTORE, req).await?;
+        Ok(res.datastore.into_iter()
+            .map(|d| model::ListdatastoreDatastore {
+                key: d.key,
+                generation: d.generation,
+                hex: d.hex.map(hex::encode),
+                string: d.string,
+            })
+            .collect())
+    }
+
+    /// This is spaz's own bookkeeping, so it happens in dry-run mode too
+    async fn datastore(&self, key: Vec<String>, value: String) -> Result<(), Error> {
+        let req = proto::DatastoreRequest { key, string: Some(value), mode: Some(proto::DATASTORE_MUST_CREATE) };
+        let _: proto::DatastoreResponse = self.unary(DATASTORE, req).await?;
+        Ok(())
+    }
+
+    async fn protected_channels(&self) -> Result<Vec<String>, Error> {
+        Ok(self.list_datastore(&PROTECTED_DATASTORE_KEY).await?
+            .into_iter()
+            .filter_map(|d| d.key.get(PROTECTED_DATASTORE_KEY.len()).cloned())
+            .collect())
+    }
+
+    async fn keysend_node(&self, pubkey: PublicKey, amount_msat: Amount) -> Result<model::KeysendResponse, Error> {
+        log::info!("Keysending node {:?}, {:?}", pubkey, amount_msat);
+        if self.skip_for_dry_run("keysend", serde_json::json!({ "destination": pubkey.to_string(), "amount_msat": amount_msat.msat() })) {
+            return dry_run_keysend(pubkey, amount_msat)
+        }
+        let req = proto::KeysendRequest { destination: pubkey.serialize().to_vec(), amount_msat: amount(amount_msat.msat()) };
+        let res: proto::KeysendResponse = self.unary(KEYSEND, req).await?;
+        log::debug!("Keysend response {:?}", &res);
+        Ok(model::KeysendResponse {
+            payment_preimage: cln_rpc::primitives::Secret::try_from(res.payment_preimage)?,
+            destination: res.destination.map(|d| PublicKey::from_slice(&d)).transpose()?,
+            payment_hash: Sha256::from_slice(&res.payment_hash)?,
+            created_at: res.created_at,
+            parts: res.parts,
+            amount_msat: cln_rpc::primitives::Amount::from_msat(msat(res.amount_msat)),
+            amount_sent_msat: cln_rpc::primitives::Amount::from_msat(msat(res.amount_sent_msat)),
+            warning_partial_completion: res.warning_partial_completion,
+            status: model::KeysendStatus::try_from(res.status)?,
+        })
+    }
+
+    async fn route_and_sendpay(&self, pubkey: PublicKey, amount_msat: u64, payment_hash: Sha256) -> Result<u64, Error> {
+        let route_req = proto::GetrouteRequest { id: pubkey.serialize().to_vec(), riskfactor: 1, amount_msat: amount(amount_msat) };
+        let route_res: proto::GetrouteResponse = self.unary(GET_ROUTE, route_req).await?;
+        log::debug!("Get route response: {:?}", route_res);
+
+        // The first hop carries the amount plus every fee after it
+        let fees_msat = route_res.route.first().map(|hop| msat(hop.amount_msat.clone()).saturating_sub(amount_msat)).unwrap_or_default();
+        let route: Vec<proto::SendpayRoute> = route_res.route.into_iter()
+            .map(|hop| proto::SendpayRoute { id: hop.id, delay: hop.delay, channel: hop.channel, amount_msat: hop.amount_msat })
+            .collect();
+        let dry_run_params = serde_json::json!({
+            "destination": pubkey.to_string(),
+            "amount_msat": amount_msat,
+            "payment_hash": payment_hash.to_string(),
+            "hops": route.len(),
+        });
+        if self.skip_for_dry_run("sendpay", dry_run_params) {
+            return Ok(fees_msat)
+        }
+
+        let req = proto::SendpayRequest {
+            route,
+            payment_hash: payment_hash.into_inner().to_vec(),
+            payment_secret: Some((1..=32).collect()),
+            amount_msat: amount(amount_msat),
+        };
+        let res: proto::SendpayResponse = self.unary(SEND_PAY, req).await?;
+        log::debug!("poking response {:?}", &res);
+        Ok(fees_msat)
+    }
+
+    async fn set_channel_fee(&self, short_channel_id: &str, fee_ppm: u32, fee_base_msat: u64) -> Result<(), Error> {
+        if self.skip_for_dry_run("setchannel", serde_json::json!({ "id": short_channel_id, "feeppm": fee_ppm, "feebase": fee_base_msat })) {
+            return Ok(())
+        }
+        let req = proto::SetchannelRequest { id: short_channel_id.to_string(), feebase: amount(fee_base_msat), feeppm: Some(fee_ppm) };
+        let _: proto::SetchannelResponse = self.unary(SET_CHANNEL, req).await?;
+        log::info!("Set channel: {} to {}ppm + {}msat", short_channel_id, fee_ppm, fee_base_msat);
+        Ok(())
+    }
+
+    async fn open_channel_to_node(&self, node: Node, size: u64) -> Result<String, Error> {
+        let ipv4_address = match &node.addresses {
+            Some(addresses) => addresses.iter()
+                .find(|a| matches!(a.item_type, ListnodesNodesAddressType::IPV4))
+                .cloned(),
+            None => {
+                log::info!("Node does not have any addresses, bypassing");
+                return Err(MyCustomError::NodeNotAddressableError.into())
+            }
+        };
+        let dry_run_params = serde_json::json!({
+            "id": node.nodeid.to_string(),
+            "amount_sat": size,
+            "host": ipv4_address.as_ref().and_then(|a| a.address.clone()),
+        });
+        if self.skip_for_dry_run("fundchannel", dry_run_params) {
+            return Ok(String::new())
+        }
+        if let Some(address) = ipv4_address {
+            let req = proto::ConnectRequest { id: node.nodeid.to_string(), host: address.address, port: Some(address.port.into()) };
+            match self.unary::<_, proto::ConnectResponse>(CONNECT_PEER, req).await {
+                Ok(_) => log::info!("Peering success {}", node.nodeid),
+                Err(_) => return Err(MyCustomError::ConnectionFailedError.into()),
+            }
+        }
+        log::info!("Opening channel (PeerID: {}, Size: {})", node.nodeid, size);
+        let req = proto::FundchannelRequest {
+            id: node.nodeid.serialize().to_vec(),
+            amount: Some(proto::AmountOrAll { amount: amount(size * 1000) }),
+        };
+        match self.unary::<_, proto::FundchannelResponse>(FUND_CHANNEL, req).await {
+            Ok(res) => {
+                log::info!("Opened channel: {:?}", res);
+                Ok(hex::encode(res.txid))
+            }
+            Err(e) => {
+                log::error!("Unable to open channel: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn close_channel(&self, short_channel_id: &str) -> Result<Option<String>, Error> {
+        if self.skip_for_dry_run("close", serde_json::json!({ "id": short_channel_id })) {
+            return Ok(None)
+        }
+        let res: proto::CloseResponse = self.unary(CLOSE, proto::CloseRequest { id: short_channel_id.to_string() }).await?;
+        log::debug!("Close response {:?}", &res);
+        Ok(res.txid.map(hex::encode))
+    }
+
+    async fn disconnect_peer(&self, pubkey: PublicKey) -> Result<(), Error> {
+        log::info!("Disconnecting from peer: {:?}", pubkey);
+        if self.skip_for_dry_run("disconnect", serde_json::json!({ "id": pubkey.to_string(), "force": true })) {
+            return Ok(())
+        }
+        let req = proto::DisconnectRequest { id: pubkey.serialize().to_vec(), force: Some(true) };
+        let _: proto::DisconnectResponse = self.unary(DISCONNECT, req).await?;
+        Ok(())
+    }
+
+    async fn ping_peer(&self, pubkey: PublicKey, len: u32, pongbytes: u32) -> Result<(), Error> {
+        if self.skip_for_dry_run("ping", serde_json::json!({ "id": pubkey.to_string(), "len": len, "pongbytes": pongbytes })) {
+            return Ok(())
+        }
+        let req = proto::PingRequest { id: pubkey.serialize().to_vec(), len: Some(len), pongbytes: Some(pongbytes) };
+        let res: proto::PingResponse = self.unary(PING, req).await?;
+        log::debug!("Ping response {:?}", res);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use cln_rpc::primitives::PublicKey;
+use cln_rpc::{Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::rpc_pool::{read_only, RpcPool};
+use crate::ClnTransport;
+
+/// A remote CLN node spaz drives over commando, from a `[[commando]]` table
+/// of the daemon's config file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandoConfig {
+    /// The remote node, which must be a peer of the local node
+    pub peer_id: PublicKey,
+    /// A rune the remote node issued, allowing every method spaz calls
+    pub rune: String,
+}
+
+/// Sends requests to a peer through the local node's `commando` command,
+/// which carries them to the peer in custom messages along with the rune,
+/// and waits for the peer's reply.  As the transport of a `ClnClient`,
+/// every call it makes goes to the peer instead of the local node.
+pub struct Commando {
+    pub peer_id: PublicKey,
+    pub rune: String,
+    /// Connections to the local node, usually the pool of the local `ClnClient`
+    pub pool: Arc<RpcPool>,
+}
+
+impl Commando {
+    pub fn new(config: &CommandoConfig, pool: Arc<RpcPool>) -> Commando {
+        Commando { peer_id: config.peer_id, rune: config.rune.clone(), pool }
+    }
+}
+
+#[async_trait]
+impl ClnTransport for Commando {
+    /// Calls `request` on the peer via the local node at `rpc_path`
+    async fn call(&self, rpc_path: &str, request: Request) -> Result<Response, Error> {
+        let read_only = read_only(&request);
+        let mut request = serde_json::to_value(request)?;
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+        let params = json!({
+            "peer_id": self.peer_id.to_string(),
+            "method": method,
+            "params": request["params"].take(),
+            "rune": self.rune,
+        });
+        let result = self.pool.call_raw(rpc_path, "commando", params, read_only).await
+            .map_err(|e| anyhow!("Error calling {} on {} over commando: {}", method, self.peer_id, e))?;
+        // Tagged the way `Response` expects, as cln_rpc does for replies from the local node
+        serde_json::from_value(json!({ "method": method, "result": result }))
+            .map_err(|e| anyhow!("Malformed response to {} from {}: {}", method, self.peer_id, e))
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+use tokio::task;
+
+use crate::actions::Registry;
+use crate::breaker::Breakers;
+use crate::budget::Budget;
+use crate::cln_grpc::{ClnGrpcClient, ClnGrpcConfig};
+use crate::cln_rest::{ClnRest, ClnRestConfig};
+use crate::commando::{Commando, CommandoConfig};
+use crate::dry_run::DryRunLog;
+use crate::journal::Journal;
+use crate::lnd::{LndClient, LndConfig};
+use crate::lnd_rest::{LndRestClient, LndRestConfig};
+use crate::metrics::{self, Metrics};
+use crate::plugin::spawn_action;
+use crate::profiles::Profile;
+use crate::rng::SpazRng;
+use crate::status::Stats;
+use crate::{ClnClient, Config, LightningBackend};
+
+/// The config file of a standalone daemon.  It names either one node, in
+/// `lnd`, `lnd_rest`, `cln_grpc` or `cln_rest`, or any number of `commando`
+/// peers of the local CLN node at `spaz.rpc_path`.  `spaz` holds `Config` overrides in the same
+/// form as a profile.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DaemonFile {
+    lnd: Option<LndConfig>,
+    lnd_rest: Option<LndRestConfig>,
+    cln_grpc: Option<ClnGrpcConfig>,
+    cln_rest: Option<ClnRestConfig>,
+    #[serde(default)]
+    commando: Vec<CommandoConfig>,
+    #[serde(default)]
+    allow_mainnet: bool,
+    #[serde(default)]
+    spaz: Profile,
+}
+
+impl DaemonFile {
+    /// Reads a TOML file, or JSON if the extension is `.json`.
+    fn load(path: &Path) -> Result<DaemonFile, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read config file {}: {}", path.to_string_lossy(), e))?;
+        Ok(match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => toml::from_str(&contents)?,
+        })
+    }
+
+    /// `spaz.metrics_bind` is taken as is, since `with_updates` refuses the
+    /// keys that only take effect at startup
+    fn config(&self) -> Result<Config, Error> {
+        let mut spaz = self.spaz.clone();
+        let metrics_bind = spaz.remove("metrics_bind");
+        let mut config = Config::default().with_updates(&spaz)?;
+        config.allow_mainnet = self.allow_mainnet;
+        if let Some(bind) = metrics_bind {
+            let bind = bind.as_str().ok_or_else(|| anyhow!("metrics_bind must be a string, got {}", bind))?;
+            config.metrics_bind = bind.trim().to_string();
+        }
+        Ok(config)
+    }
+
+    /// The configured nodes, each named by its table or, for commando, its peer id
+    async fn backends(&self, config: &Config, metrics: Arc<Metrics>) -> Result<Vec<(String, Arc<dyn LightningBackend>)>, Error> {
+        let tables = [self.lnd.is_some(), self.lnd_rest.is_some(), self.cln_grpc.is_some(), self.cln_rest.is_some(), !self.commando.is_empty()];
+        match tables.iter().filter(|set| **set).count() {
+            0 => return Err(anyhow!("No backend configured, add an [lnd], [lnd_rest], [cln_grpc], [cln_rest] or [[commando]] table")),
+            1 => {}
+            _ => return Err(anyhow!("Only one of [lnd], [lnd_rest], [cln_grpc], [cln_rest] and [[commando]] may be set")),
+        }
+        if (self.lnd.is_some() || self.lnd_rest.is_some()) && config.journal == "datastore" {
+            return Err(anyhow!("LND has no datastore to journal to, set spaz.journal to a file, or to \"\" to disable the journal"))
+        }
+        if let Some(lnd) = &self.lnd {
+            let mut client = LndClient::connect(lnd).await?;
+            client.metrics = Some(metrics);
+            Ok(vec![("lnd".to_string(), Arc::new(client))])
+        } else if let Some(lnd_rest) = &self.lnd_rest {
+            let mut client = LndRestClient::new(lnd_rest)?;
+            client.metrics = Some(metrics);
+            Ok(vec![("lnd_rest".to_string(), Arc::new(client))])
+        } else if let Some(cln_grpc) = &self.cln_grpc {
+            let mut client = ClnGrpcClient::connect(cln_grpc).await?;
+            client.metrics = Some(metrics);
+            Ok(vec![("cln_grpc".to_string(), Arc::new(client))])
+        } else if let Some(cln_rest) = &self.cln_rest {
+            let transport = Arc::new(ClnRest::new(cln_rest)?);
+            Ok(vec![("cln_rest".to_string(), Arc::new(ClnClient { metrics: Some(metrics), transport: Some(transport), ..ClnClient::new(&config.rpc_path) }))])
+        } else {
+            let local = ClnClient { metrics: Some(metrics), ..ClnClient::new(&config.rpc_path) };
+            Ok(self.commando.iter()
+                .map(|c| -> (String, Arc<dyn LightningBackend>) {
+                    (c.peer_id.to_string(), Arc::new(ClnClient { transport: Some(Arc::new(Commando::new(c, local.pool.clone()))), ..local.for_path(&config.rpc_path) }))
+                })
+                .collect())
+        }
+    }
+}
+
+/// Logs to stderr, since there's no lightningd to forward logs to.  The
+/// level comes from `SPAZ_LOG`, e.g. `SPAZ_LOG=debug`, and defaults to info.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} {}: {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init_logging() {
+    let level = std::env::var("SPAZ_LOG").ok()
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(StderrLogger)).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Runs every action in `registry` against each of `backends` until
+/// ctrl-c, the way the plugin does once lightningd has started it.  Each
+/// node has breakers, budgets and stats of its own, and node `i` runs with
+/// seed `seed + i` so the nodes don't all make the same choices.  Nodes are
+/// named in the log and in journal entries, which they all append to the
+/// same journal.  Config changes need a restart, since there are no RPC
+/// methods to make them through.
+pub async fn run(backends: Vec<(String, Arc<dyn LightningBackend>)>, config: Config, registry: Registry) -> Result<(), Error> {
+    if !config.metrics_bind.is_empty() {
+        match (backends.len(), backends.first().and_then(|(_, b)| b.metrics())) {
+            (1, Some(metrics)) => {
+                let (bind, backend, config_holder) = (config.metrics_bind.clone(), backends[0].1.clone(), Arc::new(RwLock::new(config.clone())));
+                task::spawn(async move {
+                    if let Err(e) = metrics::serve(&bind, metrics, backend, config_holder).await {
+                        log::error!("Metrics endpoint on {} failed: {}", bind, e);
+                    }
+                });
+            }
+            (1, None) => log::warn!("metrics_bind is set but this backend doesn't record metrics"),
+            _ => log::warn!("metrics_bind is ignored when driving more than one node"),
+        }
+    }
+
+    let journal = Journal::default();
+    for (i, (node, backend)) in backends.into_iter().enumerate() {
+        let config = Config { seed: config.seed.wrapping_add(i as u64), ..config.clone() };
+        spawn_node(backend, config, &registry, Arc::new(journal.for_node(&node))).await;
+    }
+    log::info!("Spazzing with {:?}", registry.names());
+
+    tokio::signal::ctrl_c().await?;
+    log::info!("Shutting down");
+    Ok(())
+}
+
+async fn spawn_node(backend: Arc<dyn LightningBackend>, config: Config, registry: &Registry, journal: Arc<Journal>) {
+    let spaz_rng = Arc::new(SpazRng::new(config.seed));
+    let breakers = Arc::new(Breakers::default());
+    let budget = Arc::new(Budget::default());
+    let dry_run_log = Arc::new(DryRunLog::default());
+    let stats = Arc::new(Stats::default());
+    let node = journal.node().unwrap_or_default().to_string();
+
+    match backend.check_network(&config).await {
+        Ok(_) => log::info!("Network check passed on {}", node),
+        Err(e) => log::warn!("Mutating actions on {} will be refused until this is resolved: {}", node, e),
+    }
+
+    let config_holder = Arc::new(RwLock::new(config));
+    // Nothing cancels runs early here, but spawn_action still needs something to wait on
+    let cancellers: BTreeMap<String, Arc<Notify>> = registry.names().into_iter()
+        .map(|a| (a, Arc::new(Notify::new())))
+        .collect();
+    for action in registry.iter() {
+        spawn_action(action.clone(), config_holder.clone(), spaz_rng.clone(), breakers.clone(), budget.clone(), dry_run_log.clone(), stats.clone(), journal.clone(), backend.clone(), cancellers[action.name()].clone());
+    }
+}
+
+/// Loads the daemon config file at `path` and runs against the nodes it names
+pub async fn run_file(path: &Path, registry: Registry) -> Result<(), Error> {
+    let file = DaemonFile::load(path)?;
+    let config = file.config()?;
+    let backends = file.backends(&config, Arc::new(Metrics::default())).await?;
+    run(backends, config, registry).await
+}
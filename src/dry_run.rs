@@ -0,0 +1,44 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many intended calls to remember
+const CAPACITY: usize = 1000;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DryRunRecord {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+/// The most recent mutating calls a backend skipped because of dry-run mode
+#[derive(Default)]
+pub struct DryRunLog {
+    records: Mutex<VecDeque<DryRunRecord>>,
+}
+
+impl DryRunLog {
+    pub fn record(&self, method: &str, params: serde_json::Value) {
+        log::info!("[dry-run] Would call {} with {}", method, params);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut records = self.records.lock().unwrap();
+        if records.len() == CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(DryRunRecord { timestamp, method: method.to_string(), params });
+    }
+
+    pub fn records(&self) -> Vec<DryRunRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+use crate::{Channel, Config, Node, Peer};
+
+pub const ADDRESS_TYPES: [&str; 6] = ["dns", "ipv4", "ipv6", "torv2", "torv3", "websocket"];
+
+/// Allow/deny lists for the nodes spaz may act on.
+///
+/// A node is a valid target unless it matches any deny rule.  If any allow
+/// rule is set, it must also match at least one of them.  Alias and address
+/// rules need gossip about the node.  A node we know nothing about never
+/// matches the allow rules, and is denied outright while any alias or
+/// address deny rule is set, since we can't tell whether it would match.
+pub struct TargetFilter<'a> {
+    config: &'a Config,
+}
+
+impl<'a> TargetFilter<'a> {
+    pub fn new(config: &'a Config) -> TargetFilter<'a> {
+        TargetFilter { config }
+    }
+
+    fn has_allow_rules(&self) -> bool {
+        !self.config.allow_pubkeys.is_empty()
+            || !self.config.allow_aliases.is_empty()
+            || !self.config.allow_address_types.is_empty()
+    }
+
+    /// Whether the alias/address rules mean we need `list_nodes` to filter peers and channels
+    pub fn needs_gossip(&self) -> bool {
+        !self.config.allow_aliases.is_empty()
+            || !self.config.allow_address_types.is_empty()
+            || !self.config.deny_aliases.is_empty()
+            || !self.config.deny_address_types.is_empty()
+    }
+
+    pub fn allows(&self, pubkey: &str, node: Option<&Node>) -> bool {
+        let alias = node.and_then(|n| n.alias.as_deref());
+        let address_types: Vec<String> = node
+            .and_then(|n| n.addresses.as_ref())
+            .map(|addresses| {
+                addresses.iter()
+                    .filter_map(|a| serde_json::to_value(a.item_type).ok())
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let matches = |pubkeys: &[String], aliases: &[String], types: &[String]| {
+            pubkeys.iter().any(|p| p.eq_ignore_ascii_case(pubkey))
+                || alias.map(|a| aliases.iter().any(|g| glob_match(g, a))).unwrap_or(false)
+                || address_types.iter().any(|t| types.iter().any(|ty| ty.eq_ignore_ascii_case(t)))
+        };
+
+        let c = self.config;
+        // Without a node_announcement there is no alias or address to check
+        let unknown = node.map(|n| n.alias.is_none() && n.addresses.is_none()).unwrap_or(true);
+        if unknown && (!c.deny_aliases.is_empty() || !c.deny_address_types.is_empty()) {
+            log::debug!("{} is denied as a target, there is no gossip to check the deny rules against", pubkey);
+            return false
+        }
+        if matches(&c.deny_pubkeys, &c.deny_aliases, &c.deny_address_types) {
+            log::debug!("{} is denied as a target", pubkey);
+            return false
+        }
+        if self.has_allow_rules() && !matches(&c.allow_pubkeys, &c.allow_aliases, &c.allow_address_types) {
+            log::debug!("{} is not on the target allowlist", pubkey);
+            return false
+        }
+        true
+    }
+
+    pub fn nodes(&self, nodes: Vec<Node>) -> Vec<Node> {
+        nodes.into_iter()
+            .filter(|n| self.allows(&n.nodeid.to_string(), Some(n)))
+            .collect()
+    }
+
+    pub fn peers(&self, peers: Vec<Peer>, gossip: &BTreeMap<String, Node>) -> Vec<Peer> {
+        peers.into_iter()
+            .filter(|p| {
+                let pubkey = p.id.to_string();
+                self.allows(&pubkey, gossip.get(&pubkey))
+            })
+            .collect()
+    }
+
+    pub fn channels(&self, channels: Vec<Channel>, gossip: &BTreeMap<String, Node>) -> Vec<Channel> {
+        channels.into_iter()
+            .filter(|c| self.allows(&c.peer_id, gossip.get(&c.peer_id)))
+            .collect()
+    }
+}
+
+/// Shell-style glob supporting `*` and `?`
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const BOB: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    fn node(pubkey: &str, alias: &str) -> Node {
+        serde_json::from_value(serde_json::json!({
+            "nodeid": pubkey,
+            "alias": alias,
+            "addresses": [{ "type": "ipv4", "address": "10.0.0.1", "port": 9735 }],
+        })).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("test-*", "test-node"));
+        assert!(glob_match("test-*", "test-"));
+        assert!(!glob_match("test-*", "prod-node"));
+        assert!(glob_match("*-node", "test-node"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(glob_match("node-?", "node-1"));
+        assert!(!glob_match("node-?", "node-"));
+        assert!(!glob_match("node-?", "node-12"));
+        assert!(glob_match("?*", "x"));
+        assert!(!glob_match("", "x"));
+        assert!(glob_match("", ""));
+    }
+
+    #[test]
+    fn empty_lists_allow_everything() {
+        let config = Config::default();
+        let filter = TargetFilter::new(&config);
+        assert!(!filter.needs_gossip());
+        assert!(filter.allows(ALICE, None));
+        assert!(filter.allows(BOB, Some(&node(BOB, "bob"))));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let config = Config {
+            allow_aliases: strings(&["*"]),
+            deny_pubkeys: strings(&[ALICE]),
+            ..Config::default()
+        };
+        let filter = TargetFilter::new(&config);
+        assert!(!filter.allows(ALICE, Some(&node(ALICE, "alice"))));
+        assert!(filter.allows(BOB, Some(&node(BOB, "bob"))));
+
+        let config = Config {
+            allow_pubkeys: strings(&[ALICE]),
+            deny_aliases: strings(&["ali*"]),
+            ..Config::default()
+        };
+        let filter = TargetFilter::new(&config);
+        assert!(!filter.allows(ALICE, Some(&node(ALICE, "alice"))));
+        // Without gossip the alias rule might match, so the node is denied
+        assert!(!filter.allows(ALICE, None));
+    }
+
+    #[test]
+    fn allow_rules_are_exclusive() {
+        let config = Config { allow_pubkeys: strings(&[ALICE]), ..Config::default() };
+        let filter = TargetFilter::new(&config);
+        assert!(filter.allows(ALICE, None));
+        assert!(!filter.allows(BOB, None));
+        assert!(!filter.allows(BOB, Some(&node(BOB, "bob"))));
+    }
+
+    #[test]
+    fn aliases_and_pubkeys_match_independently() {
+        let config = Config { allow_aliases: strings(&["test-*"]), ..Config::default() };
+        let filter = TargetFilter::new(&config);
+        assert!(filter.needs_gossip());
+        assert!(filter.allows(BOB, Some(&node(BOB, "test-bob"))));
+        assert!(!filter.allows(BOB, Some(&node(BOB, "bob"))));
+        // An unknown node has no alias to match
+        assert!(!filter.allows(BOB, None));
+        // Aliases aren't matched against pubkeys, or pubkeys against aliases
+        let config = Config { allow_aliases: strings(&[BOB]), ..Config::default() };
+        assert!(!TargetFilter::new(&config).allows(BOB, Some(&node(BOB, "bob"))));
+        let config = Config { allow_pubkeys: strings(&["alice"]), ..Config::default() };
+        assert!(!TargetFilter::new(&config).allows(ALICE, Some(&node(ALICE, "alice"))));
+        // Pubkeys match regardless of case
+        let config = Config { allow_pubkeys: strings(&[&ALICE.to_uppercase()]), ..Config::default() };
+        assert!(TargetFilter::new(&config).allows(ALICE, None));
+    }
+
+    #[test]
+    fn address_types() {
+        let config = Config { deny_address_types: strings(&["IPv4"]), ..Config::default() };
+        let filter = TargetFilter::new(&config);
+        assert!(!filter.allows(ALICE, Some(&node(ALICE, "alice"))));
+        let nodes = filter.nodes(vec![node(ALICE, "alice"), node(BOB, "bob")]);
+        assert!(nodes.is_empty());
+    }
+
+    #[test]
+    fn unknown_nodes_fail_deny_rules_closed() {
+        let unannounced: Node = serde_json::from_value(serde_json::json!({ "nodeid": BOB })).unwrap();
+        for config in [
+            Config { deny_aliases: strings(&["evil-*"]), ..Config::default() },
+            Config { deny_address_types: strings(&["torv3"]), ..Config::default() },
+        ] {
+            let filter = TargetFilter::new(&config);
+            assert!(!filter.allows(ALICE, None));
+            assert!(!filter.allows(BOB, Some(&unannounced)));
+            assert!(filter.allows(BOB, Some(&node(BOB, "bob"))));
+        }
+        // Pubkey rules don't need gossip
+        let config = Config { deny_pubkeys: strings(&[BOB]), ..Config::default() };
+        assert!(TargetFilter::new(&config).allows(ALICE, None));
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Error, Result};
+use std::time::Instant;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+
+use crate::metrics::Metrics;
+
+/// Calls the unary gRPC method at `path`, e.g. `/lnrpc.Lightning/GetInfo`.
+/// Backends hand-write the prost messages they need rather than generating
+/// them, so there is no build step and only the fields spaz reads.
+pub async fn unary<Req, Resp>(channel: &Channel, path: &'static str, request: tonic::Request<Req>, metrics: Option<&Metrics>) -> Result<Resp, Error>
+where
+    Req: prost::Message + Send + Sync + 'static,
+    Resp: prost::Message + Default + Send + Sync + 'static,
+{
+    let started = Instant::now();
+    let mut grpc = tonic::client::Grpc::new(channel.clone());
+    grpc.ready().await?;
+    let res = grpc.unary(request, PathAndQuery::from_static(path), ProstCodec::default()).await;
+    observe(metrics, path, started);
+    Ok(res.map_err(|s| anyhow!("Error calling {}: {}", path, s.message()))?.into_inner())
+}
+
+/// Like `unary`, for a method that streams its responses.  Latency is
+/// measured to the start of the stream.
+pub async fn server_streaming<Req, Resp>(channel: &Channel, path: &'static str, request: tonic::Request<Req>, metrics: Option<&Metrics>) -> Result<tonic::Streaming<Resp>, Error>
+where
+    Req: prost::Message + Send + Sync + 'static,
+    Resp: prost::Message + Default + Send + Sync + 'static,
+{
+    let started = Instant::now();
+    let mut grpc = tonic::client::Grpc::new(channel.clone());
+    grpc.ready().await?;
+    let res = grpc.server_streaming(request, PathAndQuery::from_static(path), ProstCodec::default()).await;
+    observe(metrics, path, started);
+    Ok(res.map_err(|s| anyhow!("Error calling {}: {}", path, s.message()))?.into_inner())
+}
+
+/// Records the latency under the last segment of `path`, e.g. `GetInfo`
+fn observe(metrics: Option<&Metrics>, path: &str, started: Instant) {
+    if let Some(metrics) = metrics {
+        let method = path.rsplit('/').next().unwrap_or(path);
+        metrics.observe("spaz_rpc_latency_seconds", &[("method", method)], started.elapsed());
+    }
+}
+
+pub fn read_file(path: &str, what: &str) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| anyhow!("Unable to read {} {}: {}", what, path, e))
+}
@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Config, LightningBackend};
+
+/// Datastore key under which each journal entry is a child key, when `journal` is `datastore`
+pub const JOURNAL_DATASTORE_KEY: [&str; 2] = ["spaz", "journal"];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalResult {
+    Ok,
+    Error,
+    /// Not attempted, e.g. because of a circuit breaker or budget.  `error` says why.
+    Skipped,
+}
+
+impl std::str::FromStr for JournalResult {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<JournalResult, Error> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| anyhow!("Unknown result {}, expected ok, error or skipped", s))
+    }
+}
+
+/// One thing spaz did, or decided not to do, to one target
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    pub action: String,
+    pub target: Option<String>,
+    /// msat for keysend and poke, sat for open
+    pub amount: Option<u64>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub params: serde_json::Value,
+    pub result: JournalResult,
+    pub error: Option<String>,
+    pub dry_run: bool,
+    /// Which node the daemon was driving, when it drives more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn new(action: &str, target: Option<&str>, result: JournalResult) -> JournalEntry {
+        JournalEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            action: action.to_string(),
+            target: target.map(|t| t.to_string()),
+            amount: None,
+            params: serde_json::Value::Null,
+            result,
+            error: None,
+            dry_run: false,
+            node: None,
+        }
+    }
+}
+
+/// Parameters to `spaz-history`.  Every filter is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryFilter {
+    pub action: Option<String>,
+    pub target: Option<String>,
+    /// Unix timestamps in seconds, inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub result: Option<JournalResult>,
+    /// Only return the most recent `limit` matches
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    const FIELDS: [&'static str; 6] = ["action", "target", "since", "until", "result", "limit"];
+
+    /// Accepts named params, or positional ones in the order of `FIELDS`
+    pub fn from_params(params: serde_json::Value) -> Result<HistoryFilter, Error> {
+        let params = match params {
+            serde_json::Value::Array(a) if a.len() > Self::FIELDS.len() => {
+                return Err(anyhow!("Usage: spaz-history [action] [target] [since] [until] [result] [limit]"))
+            }
+            serde_json::Value::Array(a) => Self::FIELDS.iter()
+                .zip(a)
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.to_string(), v))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            serde_json::Value::Null => serde_json::json!({}),
+            p => p,
+        };
+        // lightning-cli passes positional numbers as strings
+        let params = match params {
+            serde_json::Value::Object(m) => m.into_iter()
+                .map(|(k, v)| match (k.as_str(), &v) {
+                    ("since" | "until" | "limit", serde_json::Value::String(s)) => {
+                        let n: u64 = s.parse().map_err(|_| anyhow!("{} must be a number, got {}", k, s))?;
+                        Ok((k, serde_json::json!(n)))
+                    }
+                    _ => Ok((k, v)),
+                })
+                .collect::<Result<serde_json::Map<_, _>, Error>>()?
+                .into(),
+            p => return Err(anyhow!("Unexpected params: {}", p)),
+        };
+        Ok(serde_json::from_value(params)?)
+    }
+
+    pub fn matches(&self, entry: &JournalEntry) -> bool {
+        let secs = entry.timestamp / 1000;
+        self.action.as_ref().map(|a| *a == entry.action).unwrap_or(true)
+            && self.target.as_ref().map(|t| entry.target.as_ref().map(|et| et.eq_ignore_ascii_case(t)).unwrap_or(false)).unwrap_or(true)
+            && self.since.map(|s| secs >= s).unwrap_or(true)
+            && self.until.map(|u| secs <= u).unwrap_or(true)
+            && self.result.map(|r| r == entry.result).unwrap_or(true)
+    }
+}
+
+/// Append-only record of what spaz did, so a run can be reconstructed
+/// after the fact.  Where entries go is read from the config on every
+/// write, so `journal` can be changed at runtime.
+#[derive(Default)]
+pub struct Journal {
+    /// Serializes appends to the JSONL file, shared with `for_node` journals
+    file_lock: Arc<Mutex<()>>,
+    /// Disambiguates datastore keys written in the same millisecond
+    seq: AtomicU64,
+    /// Stamped on every entry that doesn't name a node already
+    node: Option<String>,
+}
+
+impl Journal {
+    /// A journal for one of several nodes writing to the same place, which
+    /// tags its entries with `node`
+    pub fn for_node(&self, node: &str) -> Journal {
+        Journal { file_lock: self.file_lock.clone(), seq: AtomicU64::default(), node: Some(node.to_string()) }
+    }
+
+    pub fn node(&self) -> Option<&str> {
+        self.node.as_deref()
+    }
+
+    /// Records `entry`.  Failing to journal never fails the action, so errors are only logged.
+    pub fn append(&self, client: Arc<dyn LightningBackend>, config: &Config, mut entry: JournalEntry) {
+        if entry.node.is_none() {
+            entry.node = self.node.clone();
+        }
+        log::debug!("Journal: {:?}", entry);
+        match config.journal.as_str() {
+            "" => {}
+            "datastore" => {
+                let key = format!("{:013}-{:06}", entry.timestamp, self.seq.fetch_add(1, Ordering::Relaxed) % 1_000_000);
+                let key = JOURNAL_DATASTORE_KEY.iter().map(|k| k.to_string()).chain(Some(key)).collect();
+                tokio::spawn(async move {
+                    let res = match serde_json::to_string(&entry) {
+                        Ok(value) => client.datastore(key, value).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(e) = res {
+                        log::warn!("Unable to write journal entry to the datastore: {}", e);
+                    }
+                });
+            }
+            path => {
+                let _guard = self.file_lock.lock().unwrap();
+                let res = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut f| writeln!(f, "{}", serde_json::to_string(&entry)?));
+                if let Err(e) = res {
+                    log::warn!("Unable to append to journal {}: {}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Every entry, oldest first
+    pub async fn entries(&self, client: &dyn LightningBackend, config: &Config) -> Result<Vec<JournalEntry>, Error> {
+        let mut entries = match config.journal.as_str() {
+            "" => return Err(anyhow!("The journal is disabled, set spaz-journal to a file or `datastore`")),
+            "datastore" => {
+                client.list_datastore(&JOURNAL_DATASTORE_KEY).await?
+                    .into_iter()
+                    .filter_map(|d| d.string)
+                    .map(|s| Ok(serde_json::from_str(&s)?))
+                    .collect::<Result<Vec<JournalEntry>, Error>>()?
+            }
+            path => {
+                let file = match std::fs::File::open(path) {
+                    Ok(f) => f,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+                    Err(e) => return Err(anyhow!("Unable to read journal {}: {}", path, e)),
+                };
+                let _guard = self.file_lock.lock().unwrap();
+                BufReader::new(file).lines()
+                    .filter(|l| l.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+                    .map(|l| Ok(serde_json::from_str(&l?)?))
+                    .collect::<Result<Vec<JournalEntry>, Error>>()?
+            }
+        };
+        entries.sort_by_key(|e| e.timestamp);
+        Ok(entries)
+    }
+
+    pub async fn query(&self, client: &dyn LightningBackend, config: &Config, filter: &HistoryFilter) -> Result<Vec<JournalEntry>, Error> {
+        let mut entries: Vec<JournalEntry> = self.entries(client, config).await?
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect();
+        if let Some(limit) = filter.limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClnClient;
+
+    #[test]
+    fn node_journals_share_a_file_and_tag_their_entries() {
+        let path = std::env::temp_dir().join(format!("spaz-journal-test-{}.jsonl", std::process::id()));
+        let config = Config { journal: path.to_string_lossy().to_string(), ..Config::default() };
+        let client: Arc<dyn LightningBackend> = Arc::new(ClnClient::new("lightning-rpc"));
+        let journal = Journal::default();
+        let (alice, bob) = (journal.for_node("alice"), journal.for_node("bob"));
+        alice.append(client.clone(), &config, JournalEntry::new("keysend", None, JournalResult::Ok));
+        bob.append(client.clone(), &config, JournalEntry::new("ping", None, JournalResult::Ok));
+        journal.append(client, &config, JournalEntry::new("fee", None, JournalResult::Ok));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let nodes: Vec<Option<String>> = contents.lines()
+            .map(|l| serde_json::from_str::<JournalEntry>(l).unwrap().node)
+            .collect();
+        assert_eq!(nodes, vec![Some("alice".to_string()), Some("bob".to_string()), None]);
+        assert!(!contents.lines().last().unwrap().contains("node"));
+    }
+}
@@ -1,85 +1,218 @@
 extern crate serde_json;
-use bitcoin::hashes::{Hash};
-use hex;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use anyhow::{anyhow, Error, Result};
-use std::{path::Path, sync::{RwLock}};
+use async_trait::async_trait;
+use std::sync::{RwLock};
 extern crate rand;
 
-use rand::{random, Rng};
-use rand::RngCore;
-use rand::{SeedableRng};
-use rand::rngs::StdRng;
+use rand::random;
+use rand::Rng;
 
 use std::convert::TryInto;
-use cln_plugin::{Plugin, options};
+use cln_plugin::{ConfiguredPlugin, Plugin, options};
 
-use cln_rpc::{model::{self}, ClnRpc, Request};
+use cln_rpc::{model::{self}, Request, Response};
 pub use bitcoin::hashes::sha256::Hash as Sha256;
 
 use std::sync::{Arc};
+use std::collections::BTreeMap;
+
+pub mod actions;
+pub mod backend;
+pub mod breaker;
+pub mod budget;
+pub mod cln_grpc;
+pub mod cln_rest;
+pub mod commando;
+pub mod daemon;
+pub mod dry_run;
+pub mod filter;
+pub mod grpc;
+pub mod journal;
+pub mod lnd;
+pub mod lnd_common;
+pub mod lnd_rest;
+pub mod metrics;
+pub mod plugin;
+pub mod profiles;
+pub mod replay;
+pub mod rest;
+pub mod rng;
+pub mod rpc_pool;
+pub mod run_once;
+pub mod schedule;
+pub mod status;
+
+pub use backend::LightningBackend;
+use dry_run::DryRunLog;
+
+/// Datastore key under which each child key is a protected short channel id,
+/// e.g. `lightning-cli datastore '["spaz","protected","103x1x0"]' ""`
+pub const PROTECTED_DATASTORE_KEY: [&str; 2] = ["spaz", "protected"];
+use schedule::Schedule;
+
+/// Carries requests a `ClnClient` makes somewhere other than the RPC socket,
+/// e.g. to a peer over commando or to CLNRest over HTTP
+#[async_trait]
+pub trait ClnTransport: Send + Sync {
+    /// `rpc_path` is the client's socket, for transports relayed by the local node
+    async fn call(&self, rpc_path: &str, request: Request) -> Result<Response, Error>;
+}
 
 pub struct ClnClient {
-    pub rpc_path: String
+    pub rpc_path: String,
+    /// When set, mutating calls are recorded here instead of being sent to lightningd
+    pub dry_run: Option<Arc<DryRunLog>>,
+    /// When set, the latency of every call is recorded here
+    pub metrics: Option<Arc<metrics::Metrics>>,
+    pub pool: Arc<rpc_pool::RpcPool>,
+    /// When set, every call goes through this instead of the socket at `rpc_path`
+    pub transport: Option<Arc<dyn ClnTransport>>,
 }
 
 impl ClnClient {
-    async fn call(&self, request: Request) -> core::result::Result<String, Error> {
-        // let config = self.config.read().unwrap();
-        
-        let config_path = &self.rpc_path;
-        let path = Path::new(config_path);
+    pub fn new(rpc_path: &str) -> ClnClient {
+        ClnClient { rpc_path: rpc_path.to_string(), dry_run: None, metrics: None, pool: Arc::default(), transport: None }
+    }
 
-        let mut rpc = match ClnRpc::new(path).await {
-            Ok(c) => c,
-            Err(e) => {
-                log::error!("Error initializing CLN RPC - does path {} exist {}", &path.to_string_lossy(), e);
-                return Err(e)
+    /// A client for `rpc_path` that shares this client's connections,
+    /// metrics and transport, but not dry-run mode
+    pub fn for_path(&self, rpc_path: &str) -> ClnClient {
+        ClnClient {
+            rpc_path: rpc_path.to_string(),
+            dry_run: None,
+            metrics: self.metrics.clone(),
+            pool: self.pool.clone(),
+            transport: self.transport.clone(),
+        }
+    }
+
+    async fn call(&self, request: Request) -> Result<Response, Error> {
+        let started = std::time::Instant::now();
+        let method = method_name(&request);
+        let response = match &self.transport {
+            Some(transport) => {
+                let (transport, rpc_path) = (transport.clone(), self.rpc_path.clone());
+                backend::spawn_backend(async move { transport.call(&rpc_path, request).await }).await?
             }
+            None => self.pool.call(&self.rpc_path, request).await,
         };
-        let response = rpc
-            .call(request.clone())
-            .await
-            .map_err(|e| anyhow!("Error calling {:?}: {:?}", request, e))?;
-    
-        Ok(serde_json::to_string_pretty(&response)?)
-    }    
+        if let Some(metrics) = &self.metrics {
+            metrics.observe("spaz_rpc_latency_seconds", &[("method", &method)], started.elapsed());
+        }
+        response
+    }
+
+    /// Calls `request` and deserializes the `result` of the response as `T`,
+    /// which can be a `cln_rpc` model or one of the slimmer structs below.
+    /// A response of an unexpected shape is an error rather than a panic.
+    async fn call_as<T: DeserializeOwned>(&self, request: Request) -> Result<T, Error> {
+        let method = method_name(&request);
+        let mut response = serde_json::to_value(self.call(request).await?)?;
+        log::trace!("{} response: {}", method, response);
+        serde_json::from_value(response["result"].take())
+            .map_err(|e| anyhow!("Unexpected response to {}: {}", method, e))
+    }
+
+    /// The network lightningd is running on, e.g. `regtest` or `bitcoin` for mainnet
+    pub async fn network(&self) -> Result<String, Error> {
+        let req = Request::Getinfo(model::GetinfoRequest {});
+        let res: model::GetinfoResponse = self.call_as(req).await?;
+        Ok(res.network)
+    }
+
+    fn convert_route(&self, routes: Vec<model::GetrouteRoute>) -> Vec<model::SendpayRoute> {
+        routes.into_iter()
+            .map(|route| model::SendpayRoute {
+                amount_msat: route.amount_msat,
+                id: route.id,
+                delay: route.delay as u16,
+                channel: route.channel,
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl LightningBackend for ClnClient {
+    fn for_run(&self, config: &Config, dry_run: Option<Arc<DryRunLog>>) -> Arc<dyn LightningBackend> {
+        Arc::new(ClnClient { dry_run, ..self.for_path(&config.rpc_path) })
+    }
+
+    async fn network(&self) -> Result<String, Error> {
+        ClnClient::network(self).await
+    }
 
-    pub async fn disconnect_peer(&self, pubkey: cln_rpc::primitives::PublicKey) -> Result<(), Error> {
+    fn dry_run_log(&self) -> Option<&DryRunLog> {
+        self.dry_run.as_deref()
+    }
+
+    fn metrics(&self) -> Option<Arc<metrics::Metrics>> {
+        self.metrics.clone()
+    }
+
+    async fn disconnect_peer(&self, pubkey: cln_rpc::primitives::PublicKey) -> Result<(), Error> {
         log::info!("Disconnecting from peer: {:?}", pubkey);
+        if self.skip_for_dry_run("disconnect", serde_json::json!({ "id": pubkey.to_string(), "force": true })) {
+            return Ok(())
+        }
         let req = Request::Disconnect(model::DisconnectRequest { id: pubkey, force: Some(true) });
-        let _res = self.call(req).await?;
+        self.call(req).await?;
         Ok(())
     }
 
-    pub async fn list_channels(&self) -> Result<Vec<Channel>, Error> {
+    async fn list_channels(&self) -> Result<Vec<Channel>, Error> {
         let req = Request::ListFunds(model::ListfundsRequest { spent: Some(false)} );
-        let res = self.call(req).await?;
-        log::trace!("{}", &res);
-    
-        let de: ListFundsResponse = serde_json::from_str(&res).unwrap();
-        Ok(de.result.channels)
-    } 
+        let res: ListFundsResponse = self.call_as(req).await?;
+        Ok(res.channels)
+    }
 
-    pub async fn list_peers(&self) -> Result<Vec<Peer>, Error> {
+    async fn list_peers(&self) -> Result<Vec<Peer>, Error> {
         let req = Request::ListPeers(model::ListpeersRequest { id: None, level: None });
-        let res = self.call(req).await?;
-        log::trace!("{}", &res);
-        let de: ListPeersResponse = serde_json::from_str(&res).unwrap();
-        Ok(de.result.peers)
+        let res: ListPeersResponse = self.call_as(req).await?;
+        Ok(res.peers)
     }
 
 
-    pub async fn list_nodes(&self) -> Result<Vec<Node>, Error> {
+    async fn list_nodes(&self) -> Result<Vec<Node>, Error> {
         let req = Request::ListNodes(model::ListnodesRequest {id: None});
-        let res = self.call(req).await?;
-        log::trace!("{}", &res);
-        let de: ListNodesResponse = serde_json::from_str(&res).unwrap();
-        Ok(de.result.nodes)
+        let res: ListNodesResponse = self.call_as(req).await?;
+        Ok(res.nodes)
+    }
+
+    async fn list_datastore(&self, key: &[&str]) -> Result<Vec<model::ListdatastoreDatastore>, Error> {
+        let key = key.iter().map(|k| k.to_string()).collect();
+        let req = Request::ListDatastore(model::ListdatastoreRequest { key: Some(key) });
+        let res: model::ListdatastoreResponse = self.call_as(req).await?;
+        Ok(res.datastore)
+    }
+
+    /// This is spaz's own bookkeeping, so it happens in dry-run mode too
+    async fn datastore(&self, key: Vec<String>, value: String) -> Result<(), Error> {
+        let req = Request::Datastore(model::DatastoreRequest {
+            key,
+            string: Some(value),
+            hex: None,
+            mode: Some(model::DatastoreMode::MUST_CREATE),
+            generation: None,
+        });
+        self.call(req).await?;
+        Ok(())
+    }
+
+    async fn protected_channels(&self) -> Result<Vec<String>, Error> {
+        Ok(self.list_datastore(&PROTECTED_DATASTORE_KEY).await?
+            .into_iter()
+            .filter_map(|d| d.key.get(PROTECTED_DATASTORE_KEY.len()).cloned())
+            .collect())
     }
 
-    pub async fn keysend_node(&self, pubkey: cln_rpc::primitives::PublicKey, amount: Amount) -> Result<(), Error> {
+    async fn keysend_node(&self, pubkey: cln_rpc::primitives::PublicKey, amount: Amount) -> Result<model::KeysendResponse, Error> {
         log::info!("Keysending node {:?}, {:?}", pubkey, amount);
+        if self.skip_for_dry_run("keysend", serde_json::json!({ "destination": pubkey.to_string(), "amount_msat": amount.msat() })) {
+            return backend::dry_run_keysend(pubkey, amount)
+        }
         let req = Request::KeySend(model::KeysendRequest { 
             destination: pubkey, 
             amount_msat: cln_rpc::primitives::Amount::from_msat(amount.msat()),
@@ -92,29 +225,13 @@ impl ClnClient {
             extratlvs: None,
         }
         );
-        let res = self.call(req).await?;
-        log::debug!("Keysend response {}", &res);
-        let _de: KeysendResponseResponse = serde_json::from_str(&res).unwrap();
-        
-        Ok(())
+        let res: model::KeysendResponse = self.call_as(req).await?;
+        log::debug!("Keysend response {:?}", &res);
+        Ok(res)
     }
 
-    fn convert_route(&self, routes: Vec<model::GetrouteRoute>) -> Vec<model::SendpayRoute> {
-        routes.into_iter()
-            .map(|route| model::SendpayRoute {
-                amount_msat: route.amount_msat,
-                id: route.id,
-                delay: route.delay as u16,
-                channel: route.channel,
-            })
-            .collect()
-    }
-
-    
-
-    pub async fn poke_node(&self, pubkey: cln_rpc::primitives::PublicKey, amount: u64) -> Result<(), Error> {
-        log::info!("Poking node {:?}, {:?}", pubkey, amount);
-        let amount = cln_rpc::primitives::Amount::from_msat(amount);
+    async fn route_and_sendpay(&self, pubkey: cln_rpc::primitives::PublicKey, amount_msat: u64, payment_hash: Sha256) -> Result<u64, Error> {
+        let amount = cln_rpc::primitives::Amount::from_msat(amount_msat);
         let route_req = Request::GetRoute(model::GetrouteRequest {
             id: pubkey,
             amount_msat: amount,
@@ -125,29 +242,28 @@ impl ClnClient {
             exclude: None,
             maxhops: None,
         });
-        let route_res = self.call(route_req).await?;
-        log::debug!("Get route response: {}", route_res);
-        let de: GetrouteResponseResponse = serde_json::from_str(&route_res).unwrap();
-
-        // let str_value = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
-        // let bytes = hex::decode(str_value).unwrap();
-        // let payment_hash = Sha256::all_zeros();
-
-        
-        // let mut rng = rand::thread_rng();
-        let mut rng = StdRng::from_entropy();
-
-        let mut random_bytes = [0u8; 32];
-        rng.fill_bytes(&mut random_bytes);
-        // let random_string = hex::encode(random_bytes);        // let random_string = hex::encode(random_bytes);
-        let payment_hash = Sha256::from_slice(&random_bytes).unwrap();
+        let route_res: model::GetrouteResponse = self.call_as(route_req).await?;
+        log::debug!("Get route response: {:?}", route_res);
 
         let secret_value = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32];
-        let payment_secret = cln_rpc::primitives::Secret::try_from(secret_value).unwrap();
+        let payment_secret = cln_rpc::primitives::Secret::try_from(secret_value)?;
+
+        // The first hop carries the amount plus every fee after it
+        let fees_msat = route_res.route.first().map(|hop| hop.amount_msat.msat().saturating_sub(amount_msat)).unwrap_or_default();
+        let route = self.convert_route(route_res.route);
+        let dry_run_params = serde_json::json!({
+            "destination": pubkey.to_string(),
+            "amount_msat": amount.msat(),
+            "payment_hash": payment_hash.to_string(),
+            "hops": route.len(),
+        });
+        if self.skip_for_dry_run("sendpay", dry_run_params) {
+            return Ok(fees_msat)
+        }
 
         let req = Request::SendPay(model::SendpayRequest {
-            route: self.convert_route(de.result.route),
-            payment_hash: payment_hash,
+            route,
+            payment_hash,
             label: None,
             amount_msat: Some(amount),
             bolt11: None,
@@ -159,52 +275,46 @@ impl ClnClient {
         }
         );
         let res = self.call(req).await?;
-        log::debug!("poking response {}", &res);
-        // let _de: KeysendResponseResponse = serde_json::from_str(&res).unwrap();
-        
-        Ok(())
+        log::debug!("poking response {:?}", &res);
+
+        Ok(fees_msat)
     }
     
-    // Randomize fee
-    
-    pub async fn randomize_fee(&self, short_channel_id: &String) -> Result<(), Error> {
-        let random_ppm: u32 = random::<u32>() % 700 + 50;
-        let random_base: u64 = random::<u64>() % 1500 + 1;
+    async fn set_channel_fee(&self, short_channel_id: &str, fee_ppm: u32, fee_base_msat: u64) -> Result<(), Error> {
+        if self.skip_for_dry_run("setchannel", serde_json::json!({ "id": short_channel_id, "feeppm": fee_ppm, "feebase": fee_base_msat })) {
+            return Ok(())
+        }
         let req = Request::SetChannel(model::SetchannelRequest {
             id: short_channel_id.to_string(),
-            feeppm: Some(random_ppm),
-            feebase: Some(cln_rpc::primitives::Amount::from_msat(random_base)),
+            feeppm: Some(fee_ppm),
+            feebase: Some(cln_rpc::primitives::Amount::from_msat(fee_base_msat)),
             htlcmin: None,
             htlcmax: None,
             enforcedelay: None,
         });
         let res = self.call(req).await?;
         log::info!("Set channel: {:?}", res);
-    
         Ok(())
     }
-    
-    // Randomly ping peer
-    
-    pub async fn random_ping_peer(&self, pubkey: cln_rpc::primitives::PublicKey) -> Result<(), Error> {
-        
-        let ping_len: u32 = random::<u32>();
-        let pong_len: u32 = random::<u32>();
-        let req = Request::Ping(model::PingRequest { 
-            id: pubkey, 
-            len: Some(ping_len.into()),
-            pongbytes: Some(pong_len.into())
-         });
-         match self.call(req).await {
-            Ok(res) => {
-                log::info!("Pinged peer (Ping Length: {}, Pong Length: {}, Response: {})", ping_len, pong_len, res);
-                Ok(())
-            },
-            Err(e) => Err(e)
-         }    
+
+    async fn ping_peer(&self, pubkey: cln_rpc::primitives::PublicKey, len: u32, pongbytes: u32) -> Result<(), Error> {
+        if self.skip_for_dry_run("ping", serde_json::json!({ "id": pubkey.to_string(), "len": len, "pongbytes": pongbytes })) {
+            return Ok(())
+        }
+        let req = Request::Ping(model::PingRequest {
+            id: pubkey,
+            len: Some(len.into()),
+            pongbytes: Some(pongbytes.into())
+        });
+        let res = self.call(req).await?;
+        log::debug!("Ping response {:?}", res);
+        Ok(())
     }
 
-    pub async fn close_channel(&self, short_channel_id: &String) -> Result<String, Error> {
+    async fn close_channel(&self, short_channel_id: &str) -> Result<Option<String>, Error> {
+        if self.skip_for_dry_run("close", serde_json::json!({ "id": short_channel_id })) {
+            return Ok(None)
+        }
         let req = Request::Close(model::CloseRequest { 
             id: short_channel_id.to_string(),
             unilateraltimeout: None,
@@ -214,10 +324,12 @@ impl ClnClient {
             force_lease_closed: None,
             feerange: None,
         });
-        self.call(req).await
+        let res: model::CloseResponse = self.call_as(req).await?;
+        log::debug!("Close response {:?}", &res);
+        Ok(res.txid)
     }
 
-    pub async fn open_channel_to_node(&self, node: Node, size: u64) -> Result<String, Error> {
+    async fn open_channel_to_node(&self, node: Node, size: u64) -> Result<String, Error> {
         let mut ipv4_address: Option<ListnodesNodesAddress>;
         ipv4_address = None;
         match node.addresses {
@@ -234,12 +346,18 @@ impl ClnClient {
                 return Err(MyCustomError::NodeNotAddressableError.into())
             }
         }
-        if ipv4_address.is_some() {
-            let address = ipv4_address.unwrap();
+        let dry_run_params = serde_json::json!({
+            "id": node.nodeid.to_string(),
+            "amount_sat": size,
+            "host": ipv4_address.as_ref().and_then(|a| a.address.clone()),
+        });
+        if self.skip_for_dry_run("fundchannel", dry_run_params) {
+            return Ok(String::new())
+        }
+        if let Some(address) = ipv4_address {
             let req = Request::Connect(model::ConnectRequest { id: node.nodeid.to_string(), host: address.address, port: Some(address.port) });
-            match self.call(req).await {
+            match self.call_as::<model::ConnectResponse>(req).await {
                 Ok(res) => {
-                    let _de: ConnectResponseResponse = serde_json::from_str(&res).unwrap();
                     log::info!("Peering success {:?}", res);
                 },
                 Err(_e) => {
@@ -251,7 +369,7 @@ impl ClnClient {
         let amount = cln_rpc::primitives::AmountOrAll::Amount(cln_rpc::primitives::Amount::from_sat(size));
         let open_req = Request::FundChannel(model::FundchannelRequest {
             id: pubkey, 
-            amount: amount,
+            amount,
             feerate: None,
             announce: None,
             minconf: None,
@@ -264,15 +382,14 @@ impl ClnClient {
             reserve: None,
         });
         log::info!("Opening channel (PeerID: {}, Size: {})", pubkey.to_string(), size);
-        match self.call(open_req).await {
+        match self.call_as::<model::FundchannelResponse>(open_req).await {
             Ok(res) => {
                 log::info!("Opened channel: {:?}", res);
-                let de: FundChannelResponseResponse = serde_json::from_str(&res).unwrap();
-                return Ok(de.result.txid)
+                Ok(res.txid)
             },
             Err(e) => {
                 log::error!("Unable to open channel: {:?}", e);
-                return Err(e)
+                Err(e)
             }
         }
     
@@ -282,16 +399,153 @@ impl ClnClient {
     
 }
 
+/// The method of `request`, e.g. `listfunds`, for logs and metrics
+fn method_name(request: &Request) -> String {
+    serde_json::to_value(request)
+        .ok()
+        .and_then(|v| v["method"].as_str().map(|m| m.to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 // Config stuff
 
-#[derive(Debug)]
+/// The chaos behaviors built into spaz.  Each one is a `ChaosAction`; see `actions`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Open,
+    Close,
+    Fee,
+    Keysend,
+    Poke,
+    Disconnect,
+    Ping,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Open,
+        Action::Close,
+        Action::Fee,
+        Action::Keysend,
+        Action::Poke,
+        Action::Disconnect,
+        Action::Ping,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Open => "open",
+            Action::Close => "close",
+            Action::Fee => "fee",
+            Action::Keysend => "keysend",
+            Action::Poke => "poke",
+            Action::Disconnect => "disconnect",
+            Action::Ping => "ping",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Action, Error> {
+        Action::ALL.into_iter()
+            .find(|a| a.name() == s)
+            .ok_or_else(|| anyhow!("Unknown action: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub rpc_path: String,
 
     pub active: bool,
+    pub dry_run: bool,
+    pub seed: u64,
+    /// Where the action journal goes: a JSONL file path, `datastore`, or empty
+    /// to disable it.  Relative paths resolve against lightningd's working
+    /// directory, so the default is the datastore.
+    pub journal: String,
+    /// Address for the Prometheus endpoint, e.g. `127.0.0.1:9750`.  Empty disables it.  Only read at startup.
+    pub metrics_bind: String,
+
+    // Networks spaz may act on.  Mainnet (`bitcoin`) additionally needs allow_mainnet
+    pub allowed_networks: Vec<String>,
+    pub allow_mainnet: bool,
+
+    // Target allow/deny lists, see `filter::TargetFilter`
+    pub allow_pubkeys: Vec<String>,
+    pub allow_aliases: Vec<String>,
+    pub allow_address_types: Vec<String>,
+    pub deny_pubkeys: Vec<String>,
+    pub deny_aliases: Vec<String>,
+    pub deny_address_types: Vec<String>,
+
+    // Short channel ids no action may touch, on top of those under `PROTECTED_DATASTORE_KEY`
+    pub protected_channels: Vec<String>,
+
     pub open_probability: f64,
     pub close_probability: f64,
-
+    pub fee_probability: f64,
+    pub keysend_probability: f64,
+    pub poke_probability: f64,
+    pub disconnect_probability: f64,
+    pub ping_probability: f64,
+
+    pub open_enabled: bool,
+    pub close_enabled: bool,
+    pub fee_enabled: bool,
+    pub keysend_enabled: bool,
+    pub poke_enabled: bool,
+    pub disconnect_enabled: bool,
+    pub ping_enabled: bool,
+    // Enabled flags for registered actions that don't have a field above
+    pub action_enabled: BTreeMap<String, bool>,
+
+    // Scheduling.  A per-action interval of 0 means "use interval_secs"
+    pub interval_secs: f64,
+    pub schedule: Schedule,
+    pub jitter: f64,
+    pub timeout_secs: f64,
+    pub open_interval_secs: f64,
+    pub close_interval_secs: f64,
+    pub fee_interval_secs: f64,
+    pub keysend_interval_secs: f64,
+    pub poke_interval_secs: f64,
+    pub disconnect_interval_secs: f64,
+    pub ping_interval_secs: f64,
+
+    // Circuit breaker.  A threshold of 0 disables it
+    pub breaker_threshold: u32,
+    pub breaker_cooldown_secs: f64,
+    pub breaker_max_cooldown_secs: f64,
+
+    // Rolling spend budgets.  0 means unlimited
+    pub budget_sent_msat_per_hour: u64,
+    pub budget_sent_msat_per_day: u64,
+    pub budget_fees_msat_per_hour: u64,
+    pub budget_fees_msat_per_day: u64,
+    pub budget_open_sat_per_hour: u64,
+    pub budget_open_sat_per_day: u64,
+
+    // Amount ranges are [min, max) - the upper bound is exclusive
+    pub open_amount_min_sat: u64,
+    pub open_amount_max_sat: u64,
+    pub keysend_amount_min_msat: u64,
+    pub keysend_amount_max_msat: u64,
+    pub poke_amount_min_msat: u64,
+    pub poke_amount_max_msat: u64,
+    pub fee_ppm_min: u64,
+    pub fee_ppm_max: u64,
+    pub fee_base_min_msat: u64,
+    pub fee_base_max_msat: u64,
 }
 
 impl Default for Config {
@@ -299,13 +553,320 @@ impl Default for Config {
         Self { 
             active: true, 
             rpc_path: "lightning-rpc".to_string(),
+            dry_run: false,
+            seed: 0,
+            journal: "datastore".to_string(),
+            metrics_bind: String::new(),
+            allowed_networks: vec!["regtest".to_string(), "signet".to_string(), "testnet".to_string()],
+            allow_mainnet: false,
+            allow_pubkeys: vec![],
+            allow_aliases: vec![],
+            allow_address_types: vec![],
+            deny_pubkeys: vec![],
+            deny_aliases: vec![],
+            deny_address_types: vec![],
+            protected_channels: vec![],
             open_probability: 0.01,
             close_probability: 0.0005,
+            fee_probability: 0.02,
+            keysend_probability: 0.05,
+            poke_probability: 0.025,
+            disconnect_probability: 0.02,
+            ping_probability: 0.1,
+            open_enabled: true,
+            close_enabled: true,
+            fee_enabled: true,
+            keysend_enabled: true,
+            poke_enabled: true,
+            disconnect_enabled: false,
+            ping_enabled: false,
+            action_enabled: BTreeMap::new(),
+            interval_secs: 5.0,
+            schedule: Schedule::Fixed,
+            jitter: 0.0,
+            timeout_secs: 120.0,
+            open_interval_secs: 0.0,
+            close_interval_secs: 0.0,
+            fee_interval_secs: 0.0,
+            keysend_interval_secs: 0.0,
+            poke_interval_secs: 0.0,
+            disconnect_interval_secs: 0.0,
+            ping_interval_secs: 0.0,
+            breaker_threshold: 3,
+            breaker_cooldown_secs: 60.0,
+            breaker_max_cooldown_secs: 3600.0,
+            budget_sent_msat_per_hour: 0,
+            budget_sent_msat_per_day: 0,
+            budget_fees_msat_per_hour: 0,
+            budget_fees_msat_per_day: 0,
+            budget_open_sat_per_hour: 0,
+            budget_open_sat_per_day: 0,
+            open_amount_min_sat: 500_000,
+            open_amount_max_sat: 1_500_000,
+            keysend_amount_min_msat: 5_000,
+            keysend_amount_max_msat: 705_000,
+            poke_amount_min_msat: 500_000,
+            poke_amount_max_msat: 1_500_000,
+            fee_ppm_min: 50,
+            fee_ppm_max: 750,
+            fee_base_min_msat: 1,
+            fee_base_max_msat: 1_501,
+        }
+    }
+}
+
+/// Longest interval, timeout or cooldown accepted, in seconds: a year.
+/// Anything longer is a mistake, and `Duration::from_secs_f64` panics on
+/// values that don't fit in a `Duration`.
+pub const MAX_SECS: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Checks that `secs` is finite, no more than `MAX_SECS`, and positive unless `allow_zero`
+fn check_secs(name: &str, secs: f64, allow_zero: bool) -> Result<(), Error> {
+    if !secs.is_finite() || secs > MAX_SECS {
+        return Err(anyhow!("{} must be at most {} seconds, got {}", name, MAX_SECS, secs))
+    }
+    if secs < 0.0 || (secs == 0.0 && !allow_zero) {
+        let expected = if allow_zero { "must not be negative" } else { "must be positive" };
+        return Err(anyhow!("{} {}, got {}", name, expected, secs))
+    }
+    Ok(())
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        let probabilities = [
+            ("open_probability", self.open_probability),
+            ("close_probability", self.close_probability),
+            ("fee_probability", self.fee_probability),
+            ("keysend_probability", self.keysend_probability),
+            ("poke_probability", self.poke_probability),
+            ("disconnect_probability", self.disconnect_probability),
+            ("ping_probability", self.ping_probability),
+        ];
+        for (name, p) in probabilities {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(anyhow!("{} must be between 0 and 1, got {}", name, p))
+            }
+        }
+
+        check_secs("interval_secs", self.interval_secs, false)?;
+        check_secs("timeout_secs", self.timeout_secs, false)?;
+        check_secs("breaker_cooldown_secs", self.breaker_cooldown_secs, false)?;
+        check_secs("breaker_max_cooldown_secs", self.breaker_max_cooldown_secs, false)?;
+        if self.breaker_max_cooldown_secs < self.breaker_cooldown_secs {
+            return Err(anyhow!("breaker cooldowns must be positive with max >= min, got {} and {}", self.breaker_cooldown_secs, self.breaker_max_cooldown_secs))
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(anyhow!("jitter must be between 0 and 1, got {}", self.jitter))
+        }
+        for action in Action::ALL {
+            check_secs(&format!("{}_interval_secs", action), self.action_interval_secs(action), true)?;
+        }
+
+        for t in self.allow_address_types.iter().chain(self.deny_address_types.iter()) {
+            if !filter::ADDRESS_TYPES.contains(&t.to_lowercase().as_str()) {
+                return Err(anyhow!("Unknown address type {}, expected one of {:?}", t, filter::ADDRESS_TYPES))
+            }
+        }
+
+        let ranges = [
+            ("open_amount_sat", self.open_amount_min_sat, self.open_amount_max_sat),
+            ("keysend_amount_msat", self.keysend_amount_min_msat, self.keysend_amount_max_msat),
+            ("poke_amount_msat", self.poke_amount_min_msat, self.poke_amount_max_msat),
+            ("fee_ppm", self.fee_ppm_min, self.fee_ppm_max),
+            ("fee_base_msat", self.fee_base_min_msat, self.fee_base_max_msat),
+        ];
+        for (name, min, max) in ranges {
+            if min > max {
+                return Err(anyhow!("{} minimum ({}) is greater than maximum ({})", name, min, max))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn enabled(&self, action: Action) -> bool {
+        match action {
+            Action::Open => self.open_enabled,
+            Action::Close => self.close_enabled,
+            Action::Fee => self.fee_enabled,
+            Action::Keysend => self.keysend_enabled,
+            Action::Poke => self.poke_enabled,
+            Action::Disconnect => self.disconnect_enabled,
+            Action::Ping => self.ping_enabled,
         }
     }
+
+    pub fn set_enabled(&mut self, action: Action, enabled: bool) {
+        let flag = match action {
+            Action::Open => &mut self.open_enabled,
+            Action::Close => &mut self.close_enabled,
+            Action::Fee => &mut self.fee_enabled,
+            Action::Keysend => &mut self.keysend_enabled,
+            Action::Poke => &mut self.poke_enabled,
+            Action::Disconnect => &mut self.disconnect_enabled,
+            Action::Ping => &mut self.ping_enabled,
+        };
+        *flag = enabled;
+    }
+
+    pub fn probability(&self, action: Action) -> f64 {
+        match action {
+            Action::Open => self.open_probability,
+            Action::Close => self.close_probability,
+            Action::Fee => self.fee_probability,
+            Action::Keysend => self.keysend_probability,
+            Action::Poke => self.poke_probability,
+            Action::Disconnect => self.disconnect_probability,
+            Action::Ping => self.ping_probability,
+        }
+    }
+
+    fn action_interval_secs(&self, action: Action) -> f64 {
+        match action {
+            Action::Open => self.open_interval_secs,
+            Action::Close => self.close_interval_secs,
+            Action::Fee => self.fee_interval_secs,
+            Action::Keysend => self.keysend_interval_secs,
+            Action::Poke => self.poke_interval_secs,
+            Action::Disconnect => self.disconnect_interval_secs,
+            Action::Ping => self.ping_interval_secs,
+        }
+    }
+
+    /// Mean interval between runs of `action`, falling back to the global interval
+    pub fn interval_secs(&self, action: Action) -> f64 {
+        match self.action_interval_secs(action) {
+            i if i > 0.0 => i,
+            _ => self.interval_secs,
+        }
+    }
+
+    /// Whether mutating actions may run against a node on `network`
+    pub fn check_network(&self, network: &str) -> Result<(), Error> {
+        if network == "bitcoin" {
+            if self.allow_mainnet {
+                return Ok(())
+            }
+            return Err(anyhow!("Refusing to spaz on mainnet.  Set spaz-allow-mainnet=true if you really mean it."))
+        }
+        if self.allowed_networks.iter().any(|n| n == network) {
+            Ok(())
+        } else {
+            Err(anyhow!("Refusing to spaz on {}: not in allowed networks {:?}", network, self.allowed_networks))
+        }
+    }
+
+    /// Returns a copy of this config with `updates` applied, or an error if
+    /// a key is unknown, a value has the wrong type, or the result is invalid.
+    /// String values are parsed as JSON when the field is not a string, so
+    /// `lightning-cli spaz-config keysend_probability 0.2` works as expected.
+    pub fn with_updates(&self, updates: &serde_json::Map<String, serde_json::Value>) -> Result<Config, Error> {
+        let mut current = match serde_json::to_value(self)? {
+            serde_json::Value::Object(m) => m,
+            _ => return Err(anyhow!("Config did not serialize to an object")),
+        };
+        for (key, value) in updates {
+            if key == "allow_mainnet" {
+                return Err(anyhow!("allow_mainnet can only be set with the spaz-allow-mainnet option"))
+            }
+            if key == "metrics_bind" {
+                return Err(anyhow!("metrics_bind can only be set with the spaz-metrics-bind option, the listener only starts at startup"))
+            }
+            let existing = current.get(key)
+                .ok_or_else(|| anyhow!("Unknown config key: {}", key))?;
+            let value = match (existing, value) {
+                (serde_json::Value::String(_), v) => v.clone(),
+                (_, serde_json::Value::String(s)) => serde_json::from_str(s)
+                    .map_err(|_| anyhow!("Invalid value for {}: {}", key, s))?,
+                (_, v) => v.clone(),
+            };
+            current.insert(key.clone(), value);
+        }
+        let updated: Config = serde_json::from_value(serde_json::Value::Object(current))
+            .map_err(|e| anyhow!("Invalid config update: {}", e))?;
+        updated.validate()?;
+        Ok(updated)
+    }
+}
+
+/// Pick a random value in [min, max).  Returns `min` if the range is empty.
+pub fn random_in_range<R: Rng>(rng: &mut R, min: u64, max: u64) -> u64 {
+    if max <= min {
+        return min
+    }
+    rng.gen_range(min, max)
+}
+
+/// Where plugin options are read from: a started `Plugin`, or a
+/// `ConfiguredPlugin` that can still disable itself if they're invalid
+pub trait PluginOptions {
+    fn option(&self, name: &str) -> Option<options::Value>;
+}
+
+impl PluginOptions for Plugin<()> {
+    fn option(&self, name: &str) -> Option<options::Value> {
+        Plugin::option(self, name)
+    }
 }
 
-pub fn load_configuration(plugin: &Plugin<()>, config_holder: Arc<RwLock<Config>>) -> Result<(), Error> {
+impl<I, O> PluginOptions for ConfiguredPlugin<(), I, O>
+where
+    I: tokio::io::AsyncRead + Send + Unpin + 'static,
+    O: tokio::io::AsyncWrite + Send + Unpin + 'static,
+{
+    fn option(&self, name: &str) -> Option<options::Value> {
+        ConfiguredPlugin::option(self, name)
+    }
+}
+
+fn float_option(plugin: &impl PluginOptions, name: &str, default: f64) -> Result<f64, Error> {
+    match plugin.option(name) {
+        Some(options::Value::String(s)) => s.trim().parse().ok()
+            .filter(|f: &f64| f.is_finite())
+            .ok_or_else(|| anyhow!("{} is not a valid number: {:?}.", name, s)),
+        Some(options::Value::Integer(i)) => Ok(i as f64),
+        None => {
+            log::info!("Missing '{}' option.  Using default.", name);
+            Ok(default)
+        }
+        Some(o) => Err(anyhow!("{} is not a valid number: {:?}.", name, o)),
+    }
+}
+
+fn probability_option(plugin: &impl PluginOptions, name: &str, default: f64) -> Result<f64, Error> {
+    let p = float_option(plugin, name, default)?;
+    if !(0.0..=1.0).contains(&p) {
+        return Err(anyhow!("{} must be between 0 and 1: {}.", name, p))
+    }
+    Ok(p)
+}
+
+fn list_option(plugin: &impl PluginOptions, name: &str, default: Vec<String>) -> Result<Vec<String>, Error> {
+    match plugin.option(name) {
+        Some(options::Value::String(s)) => Ok(s.split(',')
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .collect()),
+        None => {
+            log::info!("Missing '{}' option.  Using default.", name);
+            Ok(default)
+        }
+        Some(o) => Err(anyhow!("{} is not a valid comma-separated list: {:?}.", name, o)),
+    }
+}
+
+fn amount_option(plugin: &impl PluginOptions, name: &str, default: u64) -> Result<u64, Error> {
+    match plugin.option(name) {
+        Some(options::Value::Integer(i)) if i >= 0 => Ok(i as u64),
+        None => {
+            log::info!("Missing '{}' option.  Using default.", name);
+            Ok(default)
+        }
+        Some(o) => Err(anyhow!("{} is not a valid non-negative integer: {:?}.", name, o)),
+    }
+}
+
+pub fn load_configuration(plugin: &impl PluginOptions, config_holder: Arc<RwLock<Config>>) -> Result<(), Error> {
     let mut c = config_holder.write().unwrap();
 
     let active = match plugin.option("spaz-on-load") {
@@ -326,6 +887,16 @@ pub fn load_configuration(plugin: &Plugin<()>, config_holder: Arc<RwLock<Config>
 
     c.active = active;
 
+    match plugin.option("spaz-dry-run") {
+        Some(options::Value::Boolean(b)) => {
+            c.dry_run = b
+        }
+        None => {
+            log::info!("Missing 'spaz-dry-run' option.  Using default.");
+        },
+        Some(o) => return Err(anyhow!("spaz-dry-run is not a valid boolean: {:?}.", o)),
+    };
+
     match plugin.option("spaz-rpc-path") {
         Some(options::Value::String(s)) => {
             c.rpc_path = s
@@ -338,24 +909,108 @@ pub fn load_configuration(plugin: &Plugin<()>, config_holder: Arc<RwLock<Config>
         }
     };
 
+    c.open_probability = probability_option(plugin, "spaz-open-probability", c.open_probability)?;
+    c.close_probability = probability_option(plugin, "spaz-close-probability", c.close_probability)?;
+    c.fee_probability = probability_option(plugin, "spaz-fee-probability", c.fee_probability)?;
+    c.keysend_probability = probability_option(plugin, "spaz-keysend-probability", c.keysend_probability)?;
+    c.poke_probability = probability_option(plugin, "spaz-poke-probability", c.poke_probability)?;
+    c.disconnect_probability = probability_option(plugin, "spaz-disconnect-probability", c.disconnect_probability)?;
+    c.ping_probability = probability_option(plugin, "spaz-ping-probability", c.ping_probability)?;
+
+    c.open_amount_min_sat = amount_option(plugin, "spaz-open-amount-min-sat", c.open_amount_min_sat)?;
+    c.open_amount_max_sat = amount_option(plugin, "spaz-open-amount-max-sat", c.open_amount_max_sat)?;
+    c.keysend_amount_min_msat = amount_option(plugin, "spaz-keysend-amount-min-msat", c.keysend_amount_min_msat)?;
+    c.keysend_amount_max_msat = amount_option(plugin, "spaz-keysend-amount-max-msat", c.keysend_amount_max_msat)?;
+    c.poke_amount_min_msat = amount_option(plugin, "spaz-poke-amount-min-msat", c.poke_amount_min_msat)?;
+    c.poke_amount_max_msat = amount_option(plugin, "spaz-poke-amount-max-msat", c.poke_amount_max_msat)?;
+    c.fee_ppm_min = amount_option(plugin, "spaz-fee-ppm-min", c.fee_ppm_min)?;
+    c.fee_ppm_max = amount_option(plugin, "spaz-fee-ppm-max", c.fee_ppm_max)?;
+    c.fee_base_min_msat = amount_option(plugin, "spaz-fee-base-min-msat", c.fee_base_min_msat)?;
+    c.fee_base_max_msat = amount_option(plugin, "spaz-fee-base-max-msat", c.fee_base_max_msat)?;
+
+    c.allowed_networks = list_option(plugin, "spaz-allowed-networks", c.allowed_networks.clone())?;
+
+    match plugin.option("spaz-allow-mainnet") {
+        Some(options::Value::Boolean(b)) => {
+            c.allow_mainnet = b
+        }
+        None => {
+            log::info!("Missing 'spaz-allow-mainnet' option.  Using default.");
+        },
+        Some(o) => return Err(anyhow!("spaz-allow-mainnet is not a valid boolean: {:?}.", o)),
+    };
+
+    c.allow_pubkeys = list_option(plugin, "spaz-allow-pubkeys", c.allow_pubkeys.clone())?;
+    c.allow_aliases = list_option(plugin, "spaz-allow-aliases", c.allow_aliases.clone())?;
+    c.allow_address_types = list_option(plugin, "spaz-allow-address-types", c.allow_address_types.clone())?;
+    c.deny_pubkeys = list_option(plugin, "spaz-deny-pubkeys", c.deny_pubkeys.clone())?;
+    c.deny_aliases = list_option(plugin, "spaz-deny-aliases", c.deny_aliases.clone())?;
+    c.deny_address_types = list_option(plugin, "spaz-deny-address-types", c.deny_address_types.clone())?;
+    c.protected_channels = list_option(plugin, "spaz-protected-channels", c.protected_channels.clone())?;
+
+    match plugin.option("spaz-journal") {
+        Some(options::Value::String(s)) => c.journal = s.trim().to_string(),
+        None => log::info!("Missing 'spaz-journal' option.  Using default."),
+        Some(o) => return Err(anyhow!("spaz-journal is not a valid string: {:?}.", o)),
+    };
+
+    match plugin.option("spaz-metrics-bind") {
+        Some(options::Value::String(s)) => c.metrics_bind = s.trim().to_string(),
+        None => log::info!("Missing 'spaz-metrics-bind' option.  Using default."),
+        Some(o) => return Err(anyhow!("spaz-metrics-bind is not a valid string: {:?}.", o)),
+    };
+
+    c.seed = match plugin.option("spaz-seed") {
+        Some(options::Value::String(s)) if s.trim().is_empty() => random(),
+        Some(options::Value::String(s)) => s.trim().parse()
+            .map_err(|_| anyhow!("spaz-seed is not a valid u64: {:?}.", s))?,
+        Some(options::Value::Integer(i)) => i as u64,
+        None => random(),
+        Some(o) => return Err(anyhow!("spaz-seed is not a valid u64: {:?}.", o)),
+    };
+    log::info!("Using seed {}", c.seed);
+
+    c.interval_secs = float_option(plugin, "spaz-interval", c.interval_secs)?;
+    c.jitter = float_option(plugin, "spaz-jitter", c.jitter)?;
+    c.timeout_secs = float_option(plugin, "spaz-timeout", c.timeout_secs)?;
+    match plugin.option("spaz-schedule") {
+        Some(options::Value::String(s)) => {
+            c.schedule = s.parse()?
+        }
+        None => {
+            log::info!("Missing 'spaz-schedule' option.  Using default.");
+        },
+        Some(o) => return Err(anyhow!("spaz-schedule is not a valid schedule: {:?}.", o)),
+    };
+    c.open_interval_secs = float_option(plugin, "spaz-open-interval", c.open_interval_secs)?;
+    c.close_interval_secs = float_option(plugin, "spaz-close-interval", c.close_interval_secs)?;
+    c.fee_interval_secs = float_option(plugin, "spaz-fee-interval", c.fee_interval_secs)?;
+    c.keysend_interval_secs = float_option(plugin, "spaz-keysend-interval", c.keysend_interval_secs)?;
+    c.poke_interval_secs = float_option(plugin, "spaz-poke-interval", c.poke_interval_secs)?;
+    c.disconnect_interval_secs = float_option(plugin, "spaz-disconnect-interval", c.disconnect_interval_secs)?;
+    c.ping_interval_secs = float_option(plugin, "spaz-ping-interval", c.ping_interval_secs)?;
+
+    c.breaker_threshold = amount_option(plugin, "spaz-breaker-threshold", c.breaker_threshold as u64)?.try_into()?;
+    c.breaker_cooldown_secs = float_option(plugin, "spaz-breaker-cooldown", c.breaker_cooldown_secs)?;
+    c.breaker_max_cooldown_secs = float_option(plugin, "spaz-breaker-max-cooldown", c.breaker_max_cooldown_secs)?;
+
+    c.budget_sent_msat_per_hour = amount_option(plugin, "spaz-budget-sent-msat-per-hour", c.budget_sent_msat_per_hour)?;
+    c.budget_sent_msat_per_day = amount_option(plugin, "spaz-budget-sent-msat-per-day", c.budget_sent_msat_per_day)?;
+    c.budget_fees_msat_per_hour = amount_option(plugin, "spaz-budget-fees-msat-per-hour", c.budget_fees_msat_per_hour)?;
+    c.budget_fees_msat_per_day = amount_option(plugin, "spaz-budget-fees-msat-per-day", c.budget_fees_msat_per_day)?;
+    c.budget_open_sat_per_hour = amount_option(plugin, "spaz-budget-open-sat-per-hour", c.budget_open_sat_per_hour)?;
+    c.budget_open_sat_per_day = amount_option(plugin, "spaz-budget-open-sat-per-day", c.budget_open_sat_per_day)?;
+
+    c.validate()?;
+
     log::info!("Configuration loaded: {:?}", c);
     Ok(())
 }
 
 // CLN Stuff
-#[derive(Debug, Deserialize)]
-struct GetrouteResponseResponse {
-    result: model::GetrouteResponse
-}
-
 // ListChannels
 #[derive(Debug, Deserialize)]
 pub struct ListFundsResponse {
-    pub result: ListFundsResponseFunds,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ListFundsResponseFunds {
     pub channels: Vec<Channel>,
 }
 
@@ -373,11 +1028,6 @@ pub struct Channel {
 
 #[derive(Debug, Deserialize)]
 pub struct ListPeersResponse {
-    pub result: ListPeersResponsePeers,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ListPeersResponsePeers {
     pub peers: Vec<Peer>,
 }
 
@@ -395,11 +1045,6 @@ pub struct Peer {
 
 #[derive(Debug, Deserialize)]
 pub struct ListNodesResponse {
-    pub result: ListNodesResponseNodes,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ListNodesResponseNodes {
     pub nodes: Vec<Node>,
 }
 
@@ -461,21 +1106,6 @@ pub struct ListnodesNodesAddress {
     pub address: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-pub struct KeysendResponseResponse { 
-    pub result: model::KeysendResponse
-}
-
-#[derive(Clone, Debug, Deserialize)]
-pub struct ConnectResponseResponse { 
-    pub result: model::ConnectResponse
-}
-
-#[derive(Clone, Debug, Deserialize)]
-pub struct FundChannelResponseResponse {
-    pub result: model::FundchannelResponse
-}
-
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Amount {
     pub msat: u64,
@@ -483,7 +1113,7 @@ pub struct Amount {
 
 impl Amount {
     pub fn from_msat(msat: u64) -> Amount {
-        Amount { msat: msat }
+        Amount { msat }
     }
     pub fn from_sat(sat: u64) -> Amount {
         Amount { msat: 1_000 * sat }
@@ -567,4 +1197,61 @@ impl fmt::Display for MyCustomError {
       MyCustomError::ConnectionFailedError => write!(f, "Could not connect to node"),
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(update: serde_json::Value) -> Result<Config, Error> {
+        match update {
+            serde_json::Value::Object(m) => Config::default().with_updates(&m),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn startup_only_keys_are_rejected() {
+        assert!(validate(serde_json::json!({ "allow_mainnet": true })).is_err());
+        assert!(validate(serde_json::json!({ "metrics_bind": "127.0.0.1:9751" })).is_err());
+    }
+
+    #[test]
+    fn timeout_must_fit_in_a_duration() {
+        assert!(validate(serde_json::json!({ "timeout_secs": 30.0 })).is_ok());
+        assert!(validate(serde_json::json!({ "timeout_secs": MAX_SECS })).is_ok());
+        assert!(validate(serde_json::json!({ "timeout_secs": 0.0 })).is_err());
+        assert!(validate(serde_json::json!({ "timeout_secs": MAX_SECS * 2.0 })).is_err());
+        assert!(validate(serde_json::json!({ "timeout_secs": 1e300 })).is_err());
+        let nan = Config { timeout_secs: f64::NAN, ..Config::default() };
+        assert!(nan.validate().is_err());
+        let inf = Config { timeout_secs: f64::INFINITY, ..Config::default() };
+        assert!(inf.validate().is_err());
+    }
+
+    #[test]
+    fn intervals_must_be_finite() {
+        assert!(validate(serde_json::json!({ "interval_secs": 0.0 })).is_err());
+        assert!(validate(serde_json::json!({ "interval_secs": 1e300 })).is_err());
+        assert!(validate(serde_json::json!({ "open_interval_secs": 0.0 })).is_ok());
+        assert!(validate(serde_json::json!({ "open_interval_secs": -1.0 })).is_err());
+        assert!(validate(serde_json::json!({ "ping_interval_secs": 1e300 })).is_err());
+        let nan = Config { interval_secs: f64::NAN, ..Config::default() };
+        assert!(nan.validate().is_err());
+        let nan = Config { fee_interval_secs: f64::NAN, ..Config::default() };
+        assert!(nan.validate().is_err());
+        let inf = Config { keysend_interval_secs: f64::INFINITY, ..Config::default() };
+        assert!(inf.validate().is_err());
+    }
+
+    #[test]
+    fn cooldowns_must_be_finite() {
+        assert!(validate(serde_json::json!({ "breaker_cooldown_secs": 10.0, "breaker_max_cooldown_secs": 20.0 })).is_ok());
+        assert!(validate(serde_json::json!({ "breaker_cooldown_secs": 30.0, "breaker_max_cooldown_secs": 20.0 })).is_err());
+        assert!(validate(serde_json::json!({ "breaker_max_cooldown_secs": 1e300 })).is_err());
+        let inf = Config { breaker_max_cooldown_secs: f64::INFINITY, ..Config::default() };
+        assert!(inf.validate().is_err());
+        let nan = Config { breaker_cooldown_secs: f64::NAN, ..Config::default() };
+        assert!(nan.validate().is_err());
+    }
+}
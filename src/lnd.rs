@@ -0,0 +1,705 @@
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use cln_rpc::model;
+use cln_rpc::primitives::PublicKey;
+use serde::Deserialize;
+use std::sync::Arc;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{ClientTlsConfig, Endpoint};
+
+use crate::backend::dry_run_keysend;
+use crate::budget::max_fee_msat;
+use crate::dry_run::DryRunLog;
+use crate::grpc;
+use crate::lnd_common::{self, chan_id, channel_point, txid_hex, Settled, KEYSEND_RECORD, PAYMENT_TIMEOUT_SECS};
+use crate::metrics::Metrics;
+use crate::{Amount, Channel, Config, LightningBackend, Node, Peer, Sha256};
+
+const GET_INFO: &str = "/lnrpc.Lightning/GetInfo";
+const LIST_CHANNELS: &str = "/lnrpc.Lightning/ListChannels";
+const LIST_PEERS: &str = "/lnrpc.Lightning/ListPeers";
+const DESCRIBE_GRAPH: &str = "/lnrpc.Lightning/DescribeGraph";
+const QUERY_ROUTES: &str = "/lnrpc.Lightning/QueryRoutes";
+const GET_CHAN_INFO: &str = "/lnrpc.Lightning/GetChanInfo";
+const UPDATE_CHANNEL_POLICY: &str = "/lnrpc.Lightning/UpdateChannelPolicy";
+const CONNECT_PEER: &str = "/lnrpc.Lightning/ConnectPeer";
+const OPEN_CHANNEL_SYNC: &str = "/lnrpc.Lightning/OpenChannelSync";
+const CLOSE_CHANNEL: &str = "/lnrpc.Lightning/CloseChannel";
+const DISCONNECT_PEER: &str = "/lnrpc.Lightning/DisconnectPeer";
+const SEND_PAYMENT_V2: &str = "/routerrpc.Router/SendPaymentV2";
+const SEND_TO_ROUTE_V2: &str = "/routerrpc.Router/SendToRouteV2";
+
+/// Just the parts of LND's `lightning.proto` and `router.proto` that spaz
+/// uses.  Fields spaz doesn't read are left out and skipped when decoding.
+pub mod proto {
+    use std::collections::HashMap;
+
+    pub const PAYMENT_SUCCEEDED: i32 = 2;
+    pub const PAYMENT_FAILED: i32 = 3;
+    pub const FEATURE_TLV_ONION_OPT: i32 = 9;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetInfoRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetInfoResponse {
+        #[prost(string, tag = "1")]
+        pub identity_pubkey: String,
+        #[prost(string, tag = "2")]
+        pub alias: String,
+        #[prost(message, repeated, tag = "16")]
+        pub chains: Vec<Chain>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Chain {
+        #[prost(string, tag = "1")]
+        pub chain: String,
+        #[prost(string, tag = "2")]
+        pub network: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListChannelsRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListChannelsResponse {
+        #[prost(message, repeated, tag = "11")]
+        pub channels: Vec<Channel>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Channel {
+        #[prost(bool, tag = "1")]
+        pub active: bool,
+        #[prost(string, tag = "2")]
+        pub remote_pubkey: String,
+        /// `txid:output`
+        #[prost(string, tag = "3")]
+        pub channel_point: String,
+        #[prost(uint64, tag = "4")]
+        pub chan_id: u64,
+        #[prost(int64, tag = "5")]
+        pub capacity: i64,
+        #[prost(int64, tag = "6")]
+        pub local_balance: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListPeersRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListPeersResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub peers: Vec<Peer>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Peer {
+        #[prost(string, tag = "1")]
+        pub pub_key: String,
+        #[prost(string, tag = "3")]
+        pub address: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChannelGraphRequest {
+        #[prost(bool, tag = "1")]
+        pub include_unannounced: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChannelGraph {
+        #[prost(message, repeated, tag = "1")]
+        pub nodes: Vec<LightningNode>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LightningNode {
+        #[prost(uint32, tag = "1")]
+        pub last_update: u32,
+        #[prost(string, tag = "2")]
+        pub pub_key: String,
+        #[prost(string, tag = "3")]
+        pub alias: String,
+        #[prost(message, repeated, tag = "4")]
+        pub addresses: Vec<NodeAddress>,
+        #[prost(string, tag = "5")]
+        pub color: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct NodeAddress {
+        #[prost(string, tag = "1")]
+        pub network: String,
+        /// `host:port`
+        #[prost(string, tag = "2")]
+        pub addr: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct QueryRoutesRequest {
+        #[prost(string, tag = "1")]
+        pub pub_key: String,
+        #[prost(int64, tag = "12")]
+        pub amt_msat: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct QueryRoutesResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub routes: Vec<Route>,
+    }
+
+    /// Sent back as-is in `SendToRouteV2`, so every field LND needs to
+    /// forward along the route is kept
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Route {
+        #[prost(uint32, tag = "1")]
+        pub total_time_lock: u32,
+        #[prost(int64, tag = "2")]
+        pub total_fees: i64,
+        #[prost(int64, tag = "3")]
+        pub total_amt: i64,
+        #[prost(message, repeated, tag = "4")]
+        pub hops: Vec<Hop>,
+        #[prost(int64, tag = "5")]
+        pub total_fees_msat: i64,
+        #[prost(int64, tag = "6")]
+        pub total_amt_msat: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Hop {
+        #[prost(uint64, tag = "1")]
+        pub chan_id: u64,
+        #[prost(int64, tag = "2")]
+        pub chan_capacity: i64,
+        #[prost(int64, tag = "3")]
+        pub amt_to_forward: i64,
+        #[prost(int64, tag = "4")]
+        pub fee: i64,
+        #[prost(uint32, tag = "5")]
+        pub expiry: u32,
+        #[prost(int64, tag = "6")]
+        pub amt_to_forward_msat: i64,
+        #[prost(int64, tag = "7")]
+        pub fee_msat: i64,
+        #[prost(string, tag = "8")]
+        pub pub_key: String,
+        #[prost(bool, tag = "9")]
+        pub tlv_payload: bool,
+        #[prost(message, optional, tag = "10")]
+        pub mpp_record: Option<MppRecord>,
+        #[prost(map = "uint64, bytes", tag = "11")]
+        pub custom_records: HashMap<u64, Vec<u8>>,
+        #[prost(bytes = "vec", tag = "13")]
+        pub metadata: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MppRecord {
+        #[prost(int64, tag = "10")]
+        pub total_amt_msat: i64,
+        #[prost(bytes = "vec", tag = "11")]
+        pub payment_addr: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChanInfoRequest {
+        #[prost(uint64, tag = "1")]
+        pub chan_id: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChannelEdge {
+        #[prost(uint64, tag = "1")]
+        pub channel_id: u64,
+        #[prost(string, tag = "2")]
+        pub chan_point: String,
+        #[prost(string, tag = "4")]
+        pub node1_pub: String,
+        #[prost(string, tag = "5")]
+        pub node2_pub: String,
+        #[prost(message, optional, tag = "7")]
+        pub node1_policy: Option<RoutingPolicy>,
+        #[prost(message, optional, tag = "8")]
+        pub node2_policy: Option<RoutingPolicy>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RoutingPolicy {
+        #[prost(uint32, tag = "1")]
+        pub time_lock_delta: u32,
+        #[prost(int64, tag = "3")]
+        pub fee_base_msat: i64,
+        #[prost(int64, tag = "4")]
+        pub fee_rate_milli_msat: i64,
+    }
+
+    /// `funding_txid_bytes` and `funding_txid_str` are a oneof; set only one
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChannelPoint {
+        #[prost(bytes = "vec", tag = "1")]
+        pub funding_txid_bytes: Vec<u8>,
+        #[prost(string, tag = "2")]
+        pub funding_txid_str: String,
+        #[prost(uint32, tag = "3")]
+        pub output_index: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PolicyUpdateRequest {
+        #[prost(message, optional, tag = "2")]
+        pub chan_point: Option<ChannelPoint>,
+        #[prost(int64, tag = "3")]
+        pub base_fee_msat: i64,
+        #[prost(uint32, tag = "5")]
+        pub time_lock_delta: u32,
+        #[prost(uint32, tag = "9")]
+        pub fee_rate_ppm: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PolicyUpdateResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub failed_updates: Vec<FailedUpdate>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FailedUpdate {
+        #[prost(string, tag = "3")]
+        pub update_error: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LightningAddress {
+        #[prost(string, tag = "1")]
+        pub pubkey: String,
+        /// `host:port`
+        #[prost(string, tag = "2")]
+        pub host: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ConnectPeerRequest {
+        #[prost(message, optional, tag = "1")]
+        pub addr: Option<LightningAddress>,
+        #[prost(bool, tag = "2")]
+        pub perm: bool,
+        #[prost(uint64, tag = "3")]
+        pub timeout: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ConnectPeerResponse {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OpenChannelRequest {
+        #[prost(bytes = "vec", tag = "2")]
+        pub node_pubkey: Vec<u8>,
+        #[prost(int64, tag = "4")]
+        pub local_funding_amount: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CloseChannelRequest {
+        #[prost(message, optional, tag = "1")]
+        pub channel_point: Option<ChannelPoint>,
+        #[prost(bool, tag = "2")]
+        pub force: bool,
+    }
+
+    /// `close_pending` and `chan_close` are a oneof
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CloseStatusUpdate {
+        #[prost(message, optional, tag = "1")]
+        pub close_pending: Option<PendingUpdate>,
+        #[prost(message, optional, tag = "3")]
+        pub chan_close: Option<ChannelCloseUpdate>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PendingUpdate {
+        #[prost(bytes = "vec", tag = "1")]
+        pub txid: Vec<u8>,
+        #[prost(uint32, tag = "2")]
+        pub output_index: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChannelCloseUpdate {
+        #[prost(bytes = "vec", tag = "1")]
+        pub closing_txid: Vec<u8>,
+        #[prost(bool, tag = "2")]
+        pub success: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DisconnectPeerRequest {
+        #[prost(string, tag = "1")]
+        pub pub_key: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DisconnectPeerResponse {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Payment {
+        #[prost(string, tag = "1")]
+        pub payment_hash: String,
+        #[prost(int64, tag = "8")]
+        pub value_msat: i64,
+        #[prost(int32, tag = "10")]
+        pub status: i32,
+        #[prost(int64, tag = "12")]
+        pub fee_msat: i64,
+        #[prost(int64, tag = "13")]
+        pub creation_time_ns: i64,
+        #[prost(message, repeated, tag = "14")]
+        pub htlcs: Vec<HtlcAttempt>,
+        #[prost(int32, tag = "16")]
+        pub failure_reason: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HtlcAttempt {
+        #[prost(int32, tag = "1")]
+        pub status: i32,
+        #[prost(message, optional, tag = "5")]
+        pub failure: Option<Failure>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Failure {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+    }
+
+    /// `routerrpc.SendPaymentRequest`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SendPaymentRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub dest: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub payment_hash: Vec<u8>,
+        #[prost(int32, tag = "6")]
+        pub timeout_seconds: i32,
+        #[prost(map = "uint64, bytes", tag = "11")]
+        pub dest_custom_records: HashMap<u64, Vec<u8>>,
+        #[prost(int64, tag = "12")]
+        pub amt_msat: i64,
+        #[prost(int64, tag = "13")]
+        pub fee_limit_msat: i64,
+        #[prost(int32, repeated, tag = "16")]
+        pub dest_features: Vec<i32>,
+    }
+
+    /// `routerrpc.SendToRouteRequest`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SendToRouteRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub payment_hash: Vec<u8>,
+        #[prost(message, optional, tag = "2")]
+        pub route: Option<Route>,
+    }
+}
+
+/// How to reach an LND node, from the `[lnd]` table of the daemon's config file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LndConfig {
+    /// gRPC endpoint, e.g. `https://127.0.0.1:10009`
+    pub address: String,
+    /// LND's `tls.cert`.  The node must present exactly this certificate.
+    pub tls_cert: String,
+    /// e.g. `.lnd/data/chain/bitcoin/regtest/admin.macaroon`
+    pub macaroon: String,
+}
+
+/// Accepts only the one self-signed certificate LND was configured with,
+/// which webpki can't validate as a root on its own
+struct PinnedCert(rustls::Certificate);
+
+impl rustls::ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        match presented_certs.first() {
+            Some(cert) if *cert == self.0 => Ok(rustls::ServerCertVerified::assertion()),
+            _ => Err(rustls::TLSError::General("LND presented a different certificate than tls_cert".to_string())),
+        }
+    }
+}
+
+/// An LND node, over gRPC with TLS and a macaroon
+#[derive(Clone)]
+pub struct LndClient {
+    channel: tonic::transport::Channel,
+    macaroon: MetadataValue<Ascii>,
+    /// When set, mutating calls are recorded here instead of being sent to LND
+    pub dry_run: Option<Arc<DryRunLog>>,
+    /// When set, the latency of every call is recorded here
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl LndClient {
+    pub async fn connect(config: &LndConfig) -> Result<LndClient, Error> {
+        let pem = grpc::read_file(&config.tls_cert, "tls_cert")?;
+        let cert = rustls::internal::pemfile::certs(&mut &pem[..])
+            .ok()
+            .and_then(|certs| certs.into_iter().next())
+            .ok_or_else(|| anyhow!("No certificate in {}", config.tls_cert))?;
+        let macaroon = grpc::read_file(&config.macaroon, "macaroon")?;
+
+        let mut tls = rustls::ClientConfig::new();
+        tls.set_protocols(&[b"h2".to_vec()]);
+        tls.dangerous().set_certificate_verifier(Arc::new(PinnedCert(cert)));
+        // The certificate is pinned, so the name only has to be a valid DNS name
+        let tls = ClientTlsConfig::new().rustls_client_config(tls).domain_name("localhost");
+        let channel = Endpoint::from_shared(config.address.clone())?
+            .tls_config(tls)?
+            .connect()
+            .await
+            .map_err(|e| anyhow!("Unable to connect to LND at {}: {}", config.address, e))?;
+        log::info!("Connected to LND at {}", config.address);
+
+        Ok(LndClient {
+            channel,
+            macaroon: MetadataValue::from_str(&hex::encode(macaroon))?,
+            dry_run: None,
+            metrics: None,
+        })
+    }
+
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert("macaroon", self.macaroon.clone());
+        request
+    }
+
+    async fn unary<Req, Resp>(&self, path: &'static str, message: Req) -> Result<Resp, Error>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        grpc::unary(&self.channel, path, self.request(message), self.metrics.as_deref()).await
+    }
+
+    async fn server_streaming<Req, Resp>(&self, path: &'static str, message: Req) -> Result<tonic::Streaming<Resp>, Error>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Resp: prost::Message + Default + Send + Sync + 'static,
+    {
+        grpc::server_streaming(&self.channel, path, self.request(message), self.metrics.as_deref()).await
+    }
+
+    async fn get_info(&self) -> Result<proto::GetInfoResponse, Error> {
+        self.unary(GET_INFO, proto::GetInfoRequest {}).await
+    }
+
+    async fn channel_edge(&self, short_channel_id: &str) -> Result<proto::ChannelEdge, Error> {
+        self.unary(GET_CHAN_INFO, proto::ChanInfoRequest { chan_id: chan_id(short_channel_id)? }).await
+    }
+}
+
+fn failure_reason(reason: i32) -> &'static str {
+    match reason {
+        1 => "timeout",
+        2 => "no route",
+        3 => "error",
+        4 => "incorrect payment details",
+        5 => "insufficient balance",
+        _ => "unknown",
+    }
+}
+
+#[async_trait]
+impl LightningBackend for LndClient {
+    fn for_run(&self, _config: &Config, dry_run: Option<Arc<DryRunLog>>) -> Arc<dyn LightningBackend> {
+        Arc::new(LndClient { dry_run, ..self.clone() })
+    }
+
+    async fn network(&self) -> Result<String, Error> {
+        let info = self.get_info().await?;
+        lnd_common::network(info.chains.first().map(|c| c.network.as_str()))
+    }
+
+    fn dry_run_log(&self) -> Option<&DryRunLog> {
+        self.dry_run.as_deref()
+    }
+
+    fn metrics(&self) -> Option<Arc<Metrics>> {
+        self.metrics.clone()
+    }
+
+    async fn list_channels(&self) -> Result<Vec<Channel>, Error> {
+        let res: proto::ListChannelsResponse = self.unary(LIST_CHANNELS, proto::ListChannelsRequest {}).await?;
+        res.channels.into_iter()
+            .map(|c| lnd_common::channel(c.remote_pubkey, c.active, &c.channel_point, c.chan_id, c.capacity, c.local_balance))
+            .collect()
+    }
+
+    async fn list_peers(&self) -> Result<Vec<Peer>, Error> {
+        let res: proto::ListPeersResponse = self.unary(LIST_PEERS, proto::ListPeersRequest {}).await?;
+        res.peers.iter().map(|p| lnd_common::peer(&p.pub_key)).collect()
+    }
+
+    async fn list_nodes(&self) -> Result<Vec<Node>, Error> {
+        let graph: proto::ChannelGraph = self.unary(DESCRIBE_GRAPH, proto::ChannelGraphRequest { include_unannounced: false }).await?;
+        graph.nodes.into_iter()
+            .map(|n| lnd_common::node(&n.pub_key, n.last_update, n.alias, &n.color, n.addresses.iter().map(|a| a.addr.as_str())))
+            .collect()
+    }
+
+    async fn keysend_node(&self, pubkey: PublicKey, amount: Amount) -> Result<model::KeysendResponse, Error> {
+        log::info!("Keysending node {:?}, {:?}", pubkey, amount);
+        if self.skip_for_dry_run("SendPaymentV2", lnd_common::keysend_params(pubkey, amount)) {
+            return dry_run_keysend(pubkey, amount)
+        }
+        let (preimage, payment_hash) = lnd_common::keysend_preimage();
+        let request = proto::SendPaymentRequest {
+            dest: pubkey.serialize().to_vec(),
+            payment_hash: payment_hash.into_inner().to_vec(),
+            timeout_seconds: PAYMENT_TIMEOUT_SECS,
+            dest_custom_records: [(KEYSEND_RECORD, preimage.to_vec())].into_iter().collect(),
+            amt_msat: amount.msat() as i64,
+            fee_limit_msat: max_fee_msat(amount.msat()) as i64,
+            dest_features: vec![proto::FEATURE_TLV_ONION_OPT],
+        };
+        let mut updates = self.server_streaming::<_, proto::Payment>(SEND_PAYMENT_V2, request).await?;
+        while let Some(payment) = updates.message().await? {
+            log::debug!("Keysend update {:?}", payment);
+            match payment.status {
+                proto::PAYMENT_SUCCEEDED => return lnd_common::keysend_response(pubkey, preimage, payment_hash, Settled {
+                    creation_time_ns: payment.creation_time_ns,
+                    parts: payment.htlcs.len(),
+                    value_msat: payment.value_msat,
+                    fee_msat: payment.fee_msat,
+                }),
+                proto::PAYMENT_FAILED => return Err(anyhow!("Keysend failed: {}", failure_reason(payment.failure_reason))),
+                _ => {}
+            }
+        }
+        Err(anyhow!("Keysend to {} ended without a result", pubkey))
+    }
+
+    async fn route_and_sendpay(&self, pubkey: PublicKey, amount_msat: u64, payment_hash: Sha256) -> Result<u64, Error> {
+        let request = proto::QueryRoutesRequest { pub_key: pubkey.to_string(), amt_msat: amount_msat as i64 };
+        let routes: proto::QueryRoutesResponse = self.unary(QUERY_ROUTES, request).await?;
+        let mut route = routes.routes.into_iter().next()
+            .ok_or_else(|| anyhow!("No route to {}", pubkey))?;
+        log::debug!("Query routes response: {:?}", route);
+        if let Some(last) = route.hops.last_mut() {
+            last.tlv_payload = true;
+            last.mpp_record = Some(proto::MppRecord {
+                total_amt_msat: amount_msat as i64,
+                payment_addr: lnd_common::poke_payment_addr(),
+            });
+        }
+        let dry_run_params = lnd_common::send_to_route_params(pubkey, amount_msat, payment_hash, route.hops.len());
+        let fees_msat = route.total_fees_msat as u64;
+        if self.skip_for_dry_run("SendToRouteV2", dry_run_params) {
+            return Ok(fees_msat)
+        }
+        let request = proto::SendToRouteRequest { payment_hash: payment_hash.into_inner().to_vec(), route: Some(route) };
+        // The destination doesn't know the payment hash, so the HTLC failing there is the expected outcome
+        let attempt: proto::HtlcAttempt = self.unary(SEND_TO_ROUTE_V2, request).await?;
+        log::debug!("poking response {:?}", attempt);
+        Ok(fees_msat)
+    }
+
+    async fn set_channel_fee(&self, short_channel_id: &str, fee_ppm: u32, fee_base_msat: u64) -> Result<(), Error> {
+        if self.skip_for_dry_run("UpdateChannelPolicy", lnd_common::fee_params(short_channel_id, fee_ppm, fee_base_msat)) {
+            return Ok(())
+        }
+        let edge = self.channel_edge(short_channel_id).await?;
+        let us = self.get_info().await?.identity_pubkey;
+        let time_lock_delta = lnd_common::time_lock_delta(
+            &us,
+            &edge.node1_pub,
+            edge.node1_policy.map(|p| p.time_lock_delta),
+            edge.node2_policy.map(|p| p.time_lock_delta),
+        );
+        let request = proto::PolicyUpdateRequest {
+            chan_point: Some(channel_point(&edge.chan_point)?),
+            base_fee_msat: fee_base_msat as i64,
+            time_lock_delta,
+            fee_rate_ppm: fee_ppm,
+        };
+        let res: proto::PolicyUpdateResponse = self.unary(UPDATE_CHANNEL_POLICY, request).await?;
+        match res.failed_updates.first() {
+            Some(failed) => Err(anyhow!("Unable to update policy of {}: {}", short_channel_id, failed.update_error)),
+            None => {
+                log::info!("Set channel: {} to {}ppm + {}msat", short_channel_id, fee_ppm, fee_base_msat);
+                Ok(())
+            }
+        }
+    }
+
+    async fn open_channel_to_node(&self, node: Node, size: u64) -> Result<String, Error> {
+        let ipv4_address = lnd_common::open_address(&node)?;
+        if self.skip_for_dry_run("OpenChannelSync", lnd_common::open_params(&node, size, ipv4_address.as_ref())) {
+            return Ok(String::new())
+        }
+        if let Some(address) = ipv4_address {
+            let request = proto::ConnectPeerRequest {
+                addr: Some(proto::LightningAddress { pubkey: node.nodeid.to_string(), host: lnd_common::connect_host(&address) }),
+                perm: false,
+                timeout: 30,
+            };
+            let res = self.unary::<_, proto::ConnectPeerResponse>(CONNECT_PEER, request).await;
+            lnd_common::connected(&node, res)?;
+        }
+        log::info!("Opening channel (PeerID: {}, Size: {})", node.nodeid, size);
+        let request = proto::OpenChannelRequest { node_pubkey: node.nodeid.serialize().to_vec(), local_funding_amount: size as i64 };
+        match self.unary::<_, proto::ChannelPoint>(OPEN_CHANNEL_SYNC, request).await {
+            Ok(point) => {
+                log::info!("Opened channel: {:?}", point);
+                if point.funding_txid_str.is_empty() {
+                    Ok(txid_hex(&point.funding_txid_bytes))
+                } else {
+                    Ok(point.funding_txid_str)
+                }
+            }
+            Err(e) => {
+                log::error!("Unable to open channel: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn close_channel(&self, short_channel_id: &str) -> Result<Option<String>, Error> {
+        if self.skip_for_dry_run("CloseChannel", lnd_common::close_params(short_channel_id)) {
+            return Ok(None)
+        }
+        let edge = self.channel_edge(short_channel_id).await?;
+        let request = proto::CloseChannelRequest { channel_point: Some(channel_point(&edge.chan_point)?), force: false };
+        let mut updates = self.server_streaming::<_, proto::CloseStatusUpdate>(CLOSE_CHANNEL, request).await?;
+        // The first update comes once the closing transaction is broadcast; LND sees the close through without us
+        let update = updates.message().await?
+            .ok_or_else(|| anyhow!("Close of {} ended without an update", short_channel_id))?;
+        log::debug!("Close response {:?}", update);
+        Ok(update.close_pending.map(|p| txid_hex(&p.txid))
+            .or_else(|| update.chan_close.map(|c| txid_hex(&c.closing_txid))))
+    }
+
+    async fn disconnect_peer(&self, pubkey: PublicKey) -> Result<(), Error> {
+        log::info!("Disconnecting from peer: {:?}", pubkey);
+        if self.skip_for_dry_run("DisconnectPeer", lnd_common::disconnect_params(pubkey)) {
+            return Ok(())
+        }
+        let _: proto::DisconnectPeerResponse = self.unary(DISCONNECT_PEER, proto::DisconnectPeerRequest { pub_key: pubkey.to_string() }).await?;
+        Ok(())
+    }
+
+    async fn ping_peer(&self, _pubkey: PublicKey, _len: u32, _pongbytes: u32) -> Result<(), Error> {
+        Err(anyhow!("LND has no RPC to ping a peer, set ping_enabled = false"))
+    }
+}
+
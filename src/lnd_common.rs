@@ -0,0 +1,285 @@
+use anyhow::{anyhow, Error, Result};
+use bitcoin::hashes::Hash;
+use cln_rpc::model;
+use cln_rpc::primitives::PublicKey;
+use serde_json::json;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use crate::lnd::proto;
+use crate::{Amount, Channel, ListnodesNodesAddress, ListnodesNodesAddressType, MyCustomError, Node, Peer, Sha256};
+
+/// Custom record carrying the preimage of a keysend payment
+pub(crate) const KEYSEND_RECORD: u64 = 5482373484;
+pub(crate) const PAYMENT_TIMEOUT_SECS: i32 = 60;
+/// Used when LND doesn't report our current policy; `UpdateChannelPolicy` always sets it
+pub(crate) const DEFAULT_TIME_LOCK_DELTA: u32 = 80;
+
+/// The network of the first chain in `GetInfo`
+pub(crate) fn network(network: Option<&str>) -> Result<String, Error> {
+    match network {
+        // CLN calls mainnet `bitcoin`, which is what the network guard checks for
+        Some("mainnet") => Ok("bitcoin".to_string()),
+        Some(network) => Ok(network.to_string()),
+        None => Err(anyhow!("LND did not report its network")),
+    }
+}
+
+/// A channel from `ListChannels`, whose amounts are in sat
+pub(crate) fn channel(remote_pubkey: String, active: bool, chan_point: &str, chan_id: u64, capacity: i64, local_balance: i64) -> Result<Channel, Error> {
+    let point = channel_point(chan_point)?;
+    Ok(Channel {
+        peer_id: remote_pubkey,
+        connected: active,
+        our_amount_msat: Amount::from_sat(local_balance as u64),
+        amount_msat: Amount::from_sat(capacity as u64),
+        funding_txid: point.funding_txid_str,
+        funding_output: point.output_index,
+        short_channel_id: Some(short_channel_id(chan_id)),
+    })
+}
+
+/// A peer from `ListPeers`.  LND only lists connected peers.
+pub(crate) fn peer(pub_key: &str) -> Result<Peer, Error> {
+    Ok(Peer { id: PublicKey::from_str(pub_key)?, connected: true })
+}
+
+/// A node from `DescribeGraph`, with its `host:port` addresses
+pub(crate) fn node<'a>(
+    pub_key: &str,
+    last_update: u32,
+    alias: String,
+    color: &str,
+    addresses: impl Iterator<Item = &'a str>,
+) -> Result<Node, Error> {
+    let addresses: Vec<_> = addresses.filter_map(node_address).collect();
+    Ok(Node {
+        nodeid: PublicKey::from_str(pub_key)?,
+        last_timestamp: Some(last_update),
+        alias: Some(alias),
+        color: Some(color.trim_start_matches('#').to_string()),
+        features: None,
+        addresses: if addresses.is_empty() { None } else { Some(addresses) },
+    })
+}
+
+/// Our current time lock delta on a channel from `GetChanInfo`, given the
+/// deltas of both ends' policies.  `UpdateChannelPolicy` always sets the time
+/// lock delta, so fee updates keep the current one.
+pub(crate) fn time_lock_delta(us: &str, node1_pub: &str, node1_delta: Option<u32>, node2_delta: Option<u32>) -> u32 {
+    let ours = if node1_pub == us { node1_delta } else { node2_delta };
+    ours.filter(|d| *d > 0).unwrap_or(DEFAULT_TIME_LOCK_DELTA)
+}
+
+/// A fresh keysend preimage and its payment hash
+pub(crate) fn keysend_preimage() -> ([u8; 32], Sha256) {
+    let preimage: [u8; 32] = rand::random();
+    (preimage, Sha256::hash(&preimage))
+}
+
+/// What `SendPaymentV2` reported for a payment that succeeded
+pub(crate) struct Settled {
+    pub creation_time_ns: i64,
+    pub parts: usize,
+    pub value_msat: i64,
+    pub fee_msat: i64,
+}
+
+pub(crate) fn keysend_response(pubkey: PublicKey, preimage: [u8; 32], payment_hash: Sha256, payment: Settled) -> Result<model::KeysendResponse, Error> {
+    Ok(model::KeysendResponse {
+        payment_preimage: cln_rpc::primitives::Secret::try_from(preimage.to_vec())?,
+        destination: Some(pubkey),
+        payment_hash,
+        created_at: payment.creation_time_ns as f64 / 1e9,
+        parts: payment.parts as u32,
+        amount_msat: cln_rpc::primitives::Amount::from_msat(payment.value_msat as u64),
+        amount_sent_msat: cln_rpc::primitives::Amount::from_msat((payment.value_msat + payment.fee_msat) as u64),
+        warning_partial_completion: None,
+        status: model::KeysendStatus::COMPLETE,
+    })
+}
+
+/// Recorded in place of a keysend in dry-run mode
+pub(crate) fn keysend_params(pubkey: PublicKey, amount: Amount) -> serde_json::Value {
+    json!({ "dest": pubkey.to_string(), "amt_msat": amount.msat(), "keysend": true })
+}
+
+/// Recorded in place of `SendToRouteV2` in dry-run mode
+pub(crate) fn send_to_route_params(pubkey: PublicKey, amount_msat: u64, payment_hash: Sha256, hops: usize) -> serde_json::Value {
+    json!({
+        "destination": pubkey.to_string(),
+        "amount_msat": amount_msat,
+        "payment_hash": payment_hash.to_string(),
+        "hops": hops,
+    })
+}
+
+/// The MPP payment address pokes send with.  The destination doesn't know
+/// the payment hash, so it fails the HTLC whatever this is.
+pub(crate) fn poke_payment_addr() -> Vec<u8> {
+    (1..=32).collect()
+}
+
+/// Recorded in place of `UpdateChannelPolicy` in dry-run mode
+pub(crate) fn fee_params(short_channel_id: &str, fee_ppm: u32, fee_base_msat: u64) -> serde_json::Value {
+    json!({ "chan_id": short_channel_id, "fee_rate_ppm": fee_ppm, "base_fee_msat": fee_base_msat })
+}
+
+/// Where to open a channel to `node`: its first IPv4 address is connected
+/// to first, if it has one.  A node without any address can't be opened to.
+pub(crate) fn open_address(node: &Node) -> Result<Option<ListnodesNodesAddress>, Error> {
+    match &node.addresses {
+        Some(addresses) => Ok(addresses.iter()
+            .find(|a| matches!(a.item_type, ListnodesNodesAddressType::IPV4))
+            .cloned()),
+        None => {
+            log::info!("Node does not have any addresses, bypassing");
+            Err(MyCustomError::NodeNotAddressableError.into())
+        }
+    }
+}
+
+/// Recorded in place of `OpenChannelSync` in dry-run mode
+pub(crate) fn open_params(node: &Node, size: u64, address: Option<&ListnodesNodesAddress>) -> serde_json::Value {
+    json!({
+        "node_pubkey": node.nodeid.to_string(),
+        "local_funding_amount": size,
+        "host": address.and_then(|a| a.address.clone()),
+    })
+}
+
+/// `host:port`, as `ConnectPeer` takes it
+pub(crate) fn connect_host(address: &ListnodesNodesAddress) -> String {
+    format!("{}:{}", address.address.as_deref().unwrap_or_default(), address.port)
+}
+
+/// The outcome of `ConnectPeer` before an open
+pub(crate) fn connected<T>(node: &Node, res: Result<T, Error>) -> Result<(), Error> {
+    match res {
+        Ok(_) => {
+            log::info!("Peering success {}", node.nodeid);
+            Ok(())
+        }
+        // Unlike CLN's connect, ConnectPeer fails for a peer we're already connected to
+        Err(e) if e.to_string().contains("already connected") => Ok(()),
+        Err(_) => Err(MyCustomError::ConnectionFailedError.into()),
+    }
+}
+
+/// Recorded in place of `CloseChannel` in dry-run mode
+pub(crate) fn close_params(short_channel_id: &str) -> serde_json::Value {
+    json!({ "chan_id": short_channel_id })
+}
+
+/// Recorded in place of `DisconnectPeer` in dry-run mode
+pub(crate) fn disconnect_params(pubkey: PublicKey) -> serde_json::Value {
+    json!({ "pub_key": pubkey.to_string() })
+}
+
+/// LND's numeric channel id as a short channel id, e.g. `103x1x0`
+pub(crate) fn short_channel_id(chan_id: u64) -> String {
+    format!("{}x{}x{}", chan_id >> 40, (chan_id >> 16) & 0xff_ffff, chan_id & 0xffff)
+}
+
+pub(crate) fn chan_id(short_channel_id: &str) -> Result<u64, Error> {
+    let parts = short_channel_id.split('x')
+        .map(|p| p.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("Invalid short channel id {}", short_channel_id))?;
+    match parts[..] {
+        // 3 bytes of block height, 3 of transaction index and 2 of output index
+        [block, tx, output] if block < 1 << 24 && tx < 1 << 24 && output < 1 << 16 => Ok(block << 40 | tx << 16 | output),
+        _ => Err(anyhow!("Invalid short channel id {}", short_channel_id)),
+    }
+}
+
+/// Parses `txid:output`
+pub(crate) fn channel_point(chan_point: &str) -> Result<proto::ChannelPoint, Error> {
+    let (txid, output) = chan_point.split_once(':')
+        .ok_or_else(|| anyhow!("Invalid channel point {}", chan_point))?;
+    Ok(proto::ChannelPoint {
+        funding_txid_str: txid.to_string(),
+        output_index: output.parse()?,
+        ..Default::default()
+    })
+}
+
+/// Txids in bytes fields are in internal byte order, the reverse of how they're shown
+pub(crate) fn txid_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes.iter().rev().copied().collect::<Vec<u8>>())
+}
+
+fn node_address(addr: &str) -> Option<ListnodesNodesAddress> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let item_type = if host.ends_with(".onion") {
+        if host.len() > 22 { ListnodesNodesAddressType::TORV3 } else { ListnodesNodesAddressType::TORV2 }
+    } else if host.starts_with('[') {
+        ListnodesNodesAddressType::IPV6
+    } else if Ipv4Addr::from_str(host).is_ok() {
+        ListnodesNodesAddressType::IPV4
+    } else {
+        ListnodesNodesAddressType::DNS
+    };
+    Some(ListnodesNodesAddress {
+        item_type,
+        port: port.parse().ok()?,
+        address: Some(host.trim_start_matches('[').trim_end_matches(']').to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const BOB: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    #[test]
+    fn chan_ids_round_trip() {
+        for scid in ["0x0x0", "103x1x0", "840000x1234x5", "16777215x16777215x65535"] {
+            assert_eq!(short_channel_id(chan_id(scid).unwrap()), scid);
+        }
+        assert_eq!(chan_id("103x1x0").unwrap(), 113_249_697_726_464);
+        assert_eq!(chan_id("16777215x16777215x65535").unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn chan_id_rejects_out_of_range_parts() {
+        for scid in ["16777216x0x0", "0x16777216x0", "0x0x65536", "99999999999x0x0", "1x2", "1x2x3x4", "1x-2x3", "", "axbxc"] {
+            assert!(chan_id(scid).is_err(), "{} accepted", scid);
+        }
+    }
+
+    #[test]
+    fn mainnet_is_called_bitcoin() {
+        assert_eq!(network(Some("mainnet")).unwrap(), "bitcoin");
+        assert_eq!(network(Some("regtest")).unwrap(), "regtest");
+        assert!(network(None).is_err());
+    }
+
+    #[test]
+    fn channels_and_nodes_convert() {
+        let channel = channel(ALICE.to_string(), true, "ab12:1", 113_249_697_726_464, 100_000, 40_000).unwrap();
+        assert_eq!(channel.short_channel_id.as_deref(), Some("103x1x0"));
+        assert_eq!((channel.funding_txid.as_str(), channel.funding_output), ("ab12", 1));
+        assert_eq!(channel.our_amount_msat.msat(), 40_000_000);
+
+        let node = node(ALICE, 7, "alice".to_string(), "#ff0000", ["10.0.0.1:9735", "[::1]:9735", "example.com:9735", "bad"].into_iter()).unwrap();
+        assert_eq!(node.color.as_deref(), Some("ff0000"));
+        let types: Vec<_> = node.addresses.as_ref().unwrap().iter().map(|a| a.item_type).collect();
+        assert!(matches!(types[..], [ListnodesNodesAddressType::IPV4, ListnodesNodesAddressType::IPV6, ListnodesNodesAddressType::DNS]));
+        assert_eq!(connect_host(&open_address(&node).unwrap().unwrap()), "10.0.0.1:9735");
+
+        let unreachable = super::node(BOB, 0, String::new(), "", std::iter::empty()).unwrap();
+        assert!(unreachable.addresses.is_none());
+        assert!(open_address(&unreachable).is_err());
+    }
+
+    #[test]
+    fn fee_updates_keep_our_time_lock_delta() {
+        assert_eq!(time_lock_delta(ALICE, ALICE, Some(40), Some(144)), 40);
+        assert_eq!(time_lock_delta(BOB, ALICE, Some(40), Some(144)), 144);
+        assert_eq!(time_lock_delta(ALICE, ALICE, None, Some(144)), DEFAULT_TIME_LOCK_DELTA);
+        assert_eq!(time_lock_delta(ALICE, ALICE, Some(0), Some(144)), DEFAULT_TIME_LOCK_DELTA);
+    }
+}
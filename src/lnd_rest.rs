@@ -0,0 +1,492 @@
+use anyhow::{anyhow, Error, Result};
+use async_trait::async_trait;
+use bitcoin::hashes::Hash;
+use cln_rpc::model;
+use cln_rpc::primitives::PublicKey;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::backend::dry_run_keysend;
+use crate::budget::max_fee_msat;
+use crate::dry_run::DryRunLog;
+use crate::lnd_common::{self, chan_id, channel_point, txid_hex, Settled, KEYSEND_RECORD, PAYMENT_TIMEOUT_SECS};
+use crate::metrics::Metrics;
+use crate::{grpc, lnd, rest};
+use crate::{Amount, Channel, Config, LightningBackend, Node, Peer, Sha256};
+
+/// Just the parts of LND's REST responses that spaz reads.  The REST proxy
+/// encodes 64-bit integers as strings and bytes as base64.
+mod json {
+    use serde::{Deserialize, Deserializer};
+    use std::str::FromStr;
+
+    /// A 64-bit integer, which the REST proxy sends as a string
+    fn int<'de, D: Deserializer<'de>, T: FromStr + Default>(deserializer: D) -> Result<T, D::Error> {
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(s) => s.parse().map_err(|_| serde::de::Error::custom(format!("invalid integer {}", s))),
+            serde_json::Value::Number(n) => n.to_string().parse().map_err(|_| serde::de::Error::custom(format!("invalid integer {}", n))),
+            _ => Ok(T::default()),
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct GetInfoResponse {
+        pub identity_pubkey: String,
+        #[serde(default)]
+        pub chains: Vec<Chain>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Chain {
+        pub network: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListChannelsResponse {
+        #[serde(default)]
+        pub channels: Vec<Channel>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Channel {
+        #[serde(default)]
+        pub active: bool,
+        pub remote_pubkey: String,
+        pub channel_point: String,
+        #[serde(deserialize_with = "int")]
+        pub chan_id: u64,
+        #[serde(default, deserialize_with = "int")]
+        pub capacity: i64,
+        #[serde(default, deserialize_with = "int")]
+        pub local_balance: i64,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ListPeersResponse {
+        #[serde(default)]
+        pub peers: Vec<Peer>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Peer {
+        pub pub_key: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ChannelGraph {
+        #[serde(default)]
+        pub nodes: Vec<LightningNode>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct LightningNode {
+        #[serde(default)]
+        pub last_update: u32,
+        pub pub_key: String,
+        #[serde(default)]
+        pub alias: String,
+        #[serde(default)]
+        pub addresses: Vec<NodeAddress>,
+        #[serde(default)]
+        pub color: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct NodeAddress {
+        /// `host:port`
+        pub addr: String,
+    }
+
+    /// Routes are sent back as-is to `/v2/router/route/send`, so they're kept as JSON
+    #[derive(Debug, Deserialize)]
+    pub struct QueryRoutesResponse {
+        #[serde(default)]
+        pub routes: Vec<serde_json::Value>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ChannelEdge {
+        pub chan_point: String,
+        pub node1_pub: String,
+        pub node1_policy: Option<RoutingPolicy>,
+        pub node2_policy: Option<RoutingPolicy>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct RoutingPolicy {
+        #[serde(default)]
+        pub time_lock_delta: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct PolicyUpdateResponse {
+        #[serde(default)]
+        pub failed_updates: Vec<FailedUpdate>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct FailedUpdate {
+        #[serde(default)]
+        pub update_error: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ChannelPoint {
+        pub funding_txid_bytes: Option<String>,
+        pub funding_txid_str: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct CloseStatusUpdate {
+        pub close_pending: Option<PendingUpdate>,
+        pub chan_close: Option<ChannelCloseUpdate>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct PendingUpdate {
+        pub txid: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ChannelCloseUpdate {
+        pub closing_txid: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Payment {
+        /// e.g. `SUCCEEDED`
+        pub status: String,
+        #[serde(default, deserialize_with = "int")]
+        pub value_msat: i64,
+        #[serde(default, deserialize_with = "int")]
+        pub fee_msat: i64,
+        #[serde(default, deserialize_with = "int")]
+        pub creation_time_ns: i64,
+        #[serde(default)]
+        pub htlcs: Vec<serde_json::Value>,
+        /// e.g. `FAILURE_REASON_NO_ROUTE`
+        #[serde(default)]
+        pub failure_reason: String,
+    }
+
+    /// One message of a streaming call: the REST proxy sends each as a line
+    /// of its own, carrying either a result or the error that ended the stream
+    #[derive(Debug, Deserialize)]
+    pub struct StreamMessage<T> {
+        pub result: Option<T>,
+        pub error: Option<StreamError>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct StreamError {
+        #[serde(default)]
+        pub message: String,
+    }
+}
+
+/// How to reach LND's REST API, from the `[lnd_rest]` table of the daemon's config file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LndRestConfig {
+    /// e.g. `https://lnd:8080`
+    pub url: String,
+    /// LND's `tls.cert`.  Not needed with `no-rest-tls`.
+    pub tls_cert: Option<String>,
+    /// e.g. `.lnd/data/chain/bitcoin/regtest/admin.macaroon`
+    pub macaroon: String,
+}
+
+/// An LND node, over its REST API with a macaroon.  Makes the same calls as
+/// `LndClient`, for nodes whose gRPC port isn't reachable.
+#[derive(Clone)]
+pub struct LndRestClient {
+    http: reqwest::Client,
+    url: String,
+    macaroon: String,
+    /// When set, mutating calls are recorded here instead of being sent to LND
+    pub dry_run: Option<Arc<DryRunLog>>,
+    /// When set, the latency of every call is recorded here
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl LndRestClient {
+    pub fn new(config: &LndRestConfig) -> Result<LndRestClient, Error> {
+        let macaroon = grpc::read_file(&config.macaroon, "macaroon")?;
+        Ok(LndRestClient {
+            http: rest::client(config.tls_cert.as_deref(), "tls_cert")?,
+            url: config.url.trim_end_matches('/').to_string(),
+            macaroon: hex::encode(macaroon),
+            dry_run: None,
+            metrics: None,
+        })
+    }
+
+    fn request(&self, http_method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http.request(http_method, format!("{}{}", self.url, path))
+            .header("Grpc-Metadata-macaroon", &self.macaroon)
+    }
+
+    /// Calls `path`, recording latency under the gRPC method it maps to, e.g. `GetInfo`
+    async fn get<T: DeserializeOwned>(&self, method: &str, path: &str) -> Result<T, Error> {
+        rest::send(self.request(reqwest::Method::GET, path), method, self.metrics.as_deref()).await
+    }
+
+    async fn post<T: DeserializeOwned>(&self, method: &str, path: &str, body: serde_json::Value) -> Result<T, Error> {
+        rest::send(self.request(reqwest::Method::POST, path).json(&body), method, self.metrics.as_deref()).await
+    }
+
+    async fn delete<T: DeserializeOwned>(&self, method: &str, path: &str) -> Result<T, Error> {
+        rest::send(self.request(reqwest::Method::DELETE, path), method, self.metrics.as_deref()).await
+    }
+
+    async fn get_info(&self) -> Result<json::GetInfoResponse, Error> {
+        self.get("GetInfo", "/v1/getinfo").await
+    }
+
+    async fn channel_edge(&self, short_channel_id: &str) -> Result<json::ChannelEdge, Error> {
+        self.get("GetChanInfo", &format!("/v1/graph/edge/{}", chan_id(short_channel_id)?)).await
+    }
+}
+
+/// The messages of a streaming call, read as they arrive
+struct Updates {
+    response: reqwest::Response,
+    buf: Vec<u8>,
+}
+
+impl Updates {
+    async fn start(request: reqwest::RequestBuilder, method: &str, metrics: Option<&Metrics>) -> Result<Updates, Error> {
+        Ok(Updates { response: rest::call(request, method, metrics).await?, buf: vec![] })
+    }
+
+    async fn message<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue
+                }
+                let message: json::StreamMessage<T> = serde_json::from_slice(&line)
+                    .map_err(|e| anyhow!("Malformed update from LND: {}", e))?;
+                return match (message.result, message.error) {
+                    (_, Some(error)) => Err(anyhow!("{}", error.message)),
+                    (Some(result), None) => Ok(Some(result)),
+                    (None, None) => Err(anyhow!("Empty update from LND")),
+                }
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                // The last message may not end in a newline
+                None if self.buf.iter().any(|b| !b.is_ascii_whitespace()) => self.buf.push(b'\n'),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Txids in bytes fields are base64 in internal byte order
+fn txid_base64(txid: &str) -> Result<String, Error> {
+    Ok(txid_hex(&base64::decode(txid)?))
+}
+
+/// e.g. `FAILURE_REASON_NO_ROUTE` as `no route`
+fn failure_reason(reason: &str) -> String {
+    reason.trim_start_matches("FAILURE_REASON_").replace('_', " ").to_lowercase()
+}
+
+#[async_trait]
+impl LightningBackend for LndRestClient {
+    fn for_run(&self, _config: &Config, dry_run: Option<Arc<DryRunLog>>) -> Arc<dyn LightningBackend> {
+        Arc::new(LndRestClient { dry_run, ..self.clone() })
+    }
+
+    async fn network(&self) -> Result<String, Error> {
+        let info = self.get_info().await?;
+        lnd_common::network(info.chains.first().map(|c| c.network.as_str()))
+    }
+
+    fn dry_run_log(&self) -> Option<&DryRunLog> {
+        self.dry_run.as_deref()
+    }
+
+    fn metrics(&self) -> Option<Arc<Metrics>> {
+        self.metrics.clone()
+    }
+
+    async fn list_channels(&self) -> Result<Vec<Channel>, Error> {
+        let res: json::ListChannelsResponse = self.get("ListChannels", "/v1/channels").await?;
+        res.channels.into_iter()
+            .map(|c| lnd_common::channel(c.remote_pubkey, c.active, &c.channel_point, c.chan_id, c.capacity, c.local_balance))
+            .collect()
+    }
+
+    async fn list_peers(&self) -> Result<Vec<Peer>, Error> {
+        let res: json::ListPeersResponse = self.get("ListPeers", "/v1/peers").await?;
+        res.peers.iter().map(|p| lnd_common::peer(&p.pub_key)).collect()
+    }
+
+    async fn list_nodes(&self) -> Result<Vec<Node>, Error> {
+        let graph: json::ChannelGraph = self.get("DescribeGraph", "/v1/graph").await?;
+        graph.nodes.into_iter()
+            .map(|n| lnd_common::node(&n.pub_key, n.last_update, n.alias, &n.color, n.addresses.iter().map(|a| a.addr.as_str())))
+            .collect()
+    }
+
+    async fn keysend_node(&self, pubkey: PublicKey, amount: Amount) -> Result<model::KeysendResponse, Error> {
+        log::info!("Keysending node {:?}, {:?}", pubkey, amount);
+        if self.skip_for_dry_run("SendPaymentV2", lnd_common::keysend_params(pubkey, amount)) {
+            return dry_run_keysend(pubkey, amount)
+        }
+        let (preimage, payment_hash) = lnd_common::keysend_preimage();
+        let body = json!({
+            "dest": base64::encode(pubkey.serialize()),
+            "payment_hash": base64::encode(payment_hash.into_inner()),
+            "timeout_seconds": PAYMENT_TIMEOUT_SECS,
+            "dest_custom_records": { KEYSEND_RECORD.to_string(): base64::encode(preimage) },
+            "amt_msat": amount.msat().to_string(),
+            "fee_limit_msat": max_fee_msat(amount.msat()).to_string(),
+            "dest_features": [lnd::proto::FEATURE_TLV_ONION_OPT],
+        });
+        let request = self.request(reqwest::Method::POST, "/v2/router/send").json(&body);
+        let mut updates = Updates::start(request, "SendPaymentV2", self.metrics.as_deref()).await?;
+        while let Some(payment) = updates.message::<json::Payment>().await? {
+            log::debug!("Keysend update {:?}", payment);
+            match payment.status.as_str() {
+                "SUCCEEDED" => return lnd_common::keysend_response(pubkey, preimage, payment_hash, Settled {
+                    creation_time_ns: payment.creation_time_ns,
+                    parts: payment.htlcs.len(),
+                    value_msat: payment.value_msat,
+                    fee_msat: payment.fee_msat,
+                }),
+                "FAILED" => return Err(anyhow!("Keysend failed: {}", failure_reason(&payment.failure_reason))),
+                _ => {}
+            }
+        }
+        Err(anyhow!("Keysend to {} ended without a result", pubkey))
+    }
+
+    async fn route_and_sendpay(&self, pubkey: PublicKey, amount_msat: u64, payment_hash: Sha256) -> Result<u64, Error> {
+        let path = format!("/v1/graph/routes/{}/0?amt_msat={}", pubkey, amount_msat);
+        let routes: json::QueryRoutesResponse = self.get("QueryRoutes", &path).await?;
+        let mut route = routes.routes.into_iter().next()
+            .ok_or_else(|| anyhow!("No route to {}", pubkey))?;
+        log::debug!("Query routes response: {}", route);
+        let hops = match route["hops"].as_array_mut() {
+            Some(hops) => hops,
+            None => return Err(anyhow!("Route to {} has no hops", pubkey)),
+        };
+        let hop_count = hops.len();
+        if let Some(last) = hops.last_mut() {
+            last["tlv_payload"] = json!(true);
+            last["mpp_record"] = json!({
+                "total_amt_msat": amount_msat.to_string(),
+                "payment_addr": base64::encode(lnd_common::poke_payment_addr()),
+            });
+        }
+        let dry_run_params = lnd_common::send_to_route_params(pubkey, amount_msat, payment_hash, hop_count);
+        let fees_msat = route["total_fees_msat"].as_str().and_then(|f| f.parse().ok()).unwrap_or_default();
+        if self.skip_for_dry_run("SendToRouteV2", dry_run_params) {
+            return Ok(fees_msat)
+        }
+        let body = json!({ "payment_hash": base64::encode(payment_hash.into_inner()), "route": route });
+        // The destination doesn't know the payment hash, so the HTLC failing there is the expected outcome
+        let attempt: serde_json::Value = self.post("SendToRouteV2", "/v2/router/route/send", body).await?;
+        log::debug!("poking response {}", attempt);
+        Ok(fees_msat)
+    }
+
+    async fn set_channel_fee(&self, short_channel_id: &str, fee_ppm: u32, fee_base_msat: u64) -> Result<(), Error> {
+        if self.skip_for_dry_run("UpdateChannelPolicy", lnd_common::fee_params(short_channel_id, fee_ppm, fee_base_msat)) {
+            return Ok(())
+        }
+        let edge = self.channel_edge(short_channel_id).await?;
+        let us = self.get_info().await?.identity_pubkey;
+        let time_lock_delta = lnd_common::time_lock_delta(
+            &us,
+            &edge.node1_pub,
+            edge.node1_policy.map(|p| p.time_lock_delta),
+            edge.node2_policy.map(|p| p.time_lock_delta),
+        );
+        let point = channel_point(&edge.chan_point)?;
+        let body = json!({
+            "chan_point": { "funding_txid_str": point.funding_txid_str, "output_index": point.output_index },
+            "base_fee_msat": fee_base_msat.to_string(),
+            "time_lock_delta": time_lock_delta,
+            "fee_rate_ppm": fee_ppm,
+        });
+        let res: json::PolicyUpdateResponse = self.post("UpdateChannelPolicy", "/v1/chanpolicy", body).await?;
+        match res.failed_updates.first() {
+            Some(failed) => Err(anyhow!("Unable to update policy of {}: {}", short_channel_id, failed.update_error)),
+            None => {
+                log::info!("Set channel: {} to {}ppm + {}msat", short_channel_id, fee_ppm, fee_base_msat);
+                Ok(())
+            }
+        }
+    }
+
+    async fn open_channel_to_node(&self, node: Node, size: u64) -> Result<String, Error> {
+        let ipv4_address = lnd_common::open_address(&node)?;
+        if self.skip_for_dry_run("OpenChannelSync", lnd_common::open_params(&node, size, ipv4_address.as_ref())) {
+            return Ok(String::new())
+        }
+        if let Some(address) = ipv4_address {
+            let body = json!({
+                "addr": { "pubkey": node.nodeid.to_string(), "host": lnd_common::connect_host(&address) },
+                "perm": false,
+                "timeout": "30",
+            });
+            let res = self.post::<serde_json::Value>("ConnectPeer", "/v1/peers", body).await;
+            lnd_common::connected(&node, res)?;
+        }
+        log::info!("Opening channel (PeerID: {}, Size: {})", node.nodeid, size);
+        let body = json!({ "node_pubkey": base64::encode(node.nodeid.serialize()), "local_funding_amount": size.to_string() });
+        match self.post::<json::ChannelPoint>("OpenChannelSync", "/v1/channels", body).await {
+            Ok(point) => {
+                log::info!("Opened channel: {:?}", point);
+                match (point.funding_txid_str, point.funding_txid_bytes) {
+                    (Some(txid), _) if !txid.is_empty() => Ok(txid),
+                    (_, Some(bytes)) => txid_base64(&bytes),
+                    _ => Err(anyhow!("LND did not return the funding txid")),
+                }
+            }
+            Err(e) => {
+                log::error!("Unable to open channel: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    async fn close_channel(&self, short_channel_id: &str) -> Result<Option<String>, Error> {
+        if self.skip_for_dry_run("CloseChannel", lnd_common::close_params(short_channel_id)) {
+            return Ok(None)
+        }
+        let edge = self.channel_edge(short_channel_id).await?;
+        let point = channel_point(&edge.chan_point)?;
+        let path = format!("/v1/channels/{}/{}", point.funding_txid_str, point.output_index);
+        let mut updates = Updates::start(self.request(reqwest::Method::DELETE, &path), "CloseChannel", self.metrics.as_deref()).await?;
+        // The first update comes once the closing transaction is broadcast; LND sees the close through without us
+        let update: json::CloseStatusUpdate = updates.message().await?
+            .ok_or_else(|| anyhow!("Close of {} ended without an update", short_channel_id))?;
+        log::debug!("Close response {:?}", update);
+        match (update.close_pending, update.chan_close) {
+            (Some(pending), _) => Ok(Some(txid_base64(&pending.txid)?)),
+            (None, Some(close)) => Ok(Some(txid_base64(&close.closing_txid)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    async fn disconnect_peer(&self, pubkey: PublicKey) -> Result<(), Error> {
+        log::info!("Disconnecting from peer: {:?}", pubkey);
+        if self.skip_for_dry_run("DisconnectPeer", lnd_common::disconnect_params(pubkey)) {
+            return Ok(())
+        }
+        let _: serde_json::Value = self.delete("DisconnectPeer", &format!("/v1/peers/{}", pubkey)).await?;
+        Ok(())
+    }
+
+    async fn ping_peer(&self, _pubkey: PublicKey, _len: u32, _pongbytes: u32) -> Result<(), Error> {
+        Err(anyhow!("LND has no RPC to ping a peer, set ping_enabled = false"))
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Error, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::{Config, LightningBackend};
+
+/// Upper bounds, in seconds, of the latency histogram buckets
+const BUCKETS: [f64; 14] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Every metric spaz exports: name, type and help text
+const METRICS: [(&str, &str, &str); 10] = [
+    ("spaz_action_runs_total", "counter", "Scheduled and on-demand action runs by result (ok, error, cancelled)"),
+    ("spaz_action_targets_total", "counter", "Attempts on individual targets by result (ok, error, skipped)"),
+    ("spaz_action_duration_seconds", "histogram", "Time taken by each action run"),
+    ("spaz_sent_msat_total", "counter", "msat sent by keysends and pokes"),
+    ("spaz_fees_msat_total", "counter", "msat paid in routing fees"),
+    ("spaz_channels_opened_total", "counter", "Channels opened by spaz"),
+    ("spaz_channels_closed_total", "counter", "Channels closed by spaz"),
+    ("spaz_rpc_latency_seconds", "histogram", "lightningd RPC latency by method"),
+    ("spaz_channels", "gauge", "Current number of channels"),
+    ("spaz_peers", "gauge", "Current number of connected peers"),
+];
+
+#[derive(Clone, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Series are keyed by metric name and rendered labels, e.g. `action="open"`
+type Series = (&'static str, String);
+
+/// Counters and histograms for the Prometheus endpoint
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<Series, u64>>,
+    histograms: Mutex<BTreeMap<Series, Histogram>>,
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    labels.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Metrics {
+    pub fn inc(&self, name: &'static str, label_pairs: &[(&str, &str)], by: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.entry((name, labels(label_pairs))).or_default() += by;
+    }
+
+    pub fn observe(&self, name: &'static str, label_pairs: &[(&str, &str)], elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry((name, labels(label_pairs))).or_default();
+        for (bucket, le) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= le {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Prometheus text format, with `gauges` (name, value) read at scrape time
+    pub fn render(&self, gauges: &[(&str, u64)]) -> String {
+        let counters = self.counters.lock().unwrap().clone();
+        let histograms = self.histograms.lock().unwrap().clone();
+        let mut out = String::new();
+        for (name, kind, help) in METRICS.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            match *kind {
+                "counter" => {
+                    for ((_, l), v) in counters.iter().filter(|((n, _), _)| n == name) {
+                        let _ = writeln!(out, "{}{} {}", name, braced(l), v);
+                    }
+                }
+                "histogram" => {
+                    for ((_, l), h) in histograms.iter().filter(|((n, _), _)| n == name) {
+                        let sep = if l.is_empty() { "" } else { "," };
+                        for (le, count) in BUCKETS.iter().zip(h.buckets) {
+                            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, l, sep, le, count);
+                        }
+                        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, l, sep, h.count);
+                        let _ = writeln!(out, "{}_sum{} {}", name, braced(l), h.sum);
+                        let _ = writeln!(out, "{}_count{} {}", name, braced(l), h.count);
+                    }
+                }
+                _ => {
+                    for (_, v) in gauges.iter().filter(|(n, _)| n == name) {
+                        let _ = writeln!(out, "{} {}", name, v);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn braced(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+/// Channel and peer counts straight from the node.  Left out of the
+/// scrape if the node can't be reached.
+async fn gauges(client: &dyn LightningBackend, config: &Config) -> Vec<(&'static str, u64)> {
+    let client = client.for_run(config, None);
+    let mut gauges = vec![];
+    match client.list_channels().await {
+        Ok(channels) => gauges.push(("spaz_channels", channels.len() as u64)),
+        Err(e) => log::debug!("Unable to count channels for metrics: {}", e),
+    }
+    match client.list_peers().await {
+        Ok(peers) => gauges.push(("spaz_peers", peers.iter().filter(|p| p.connected).count() as u64)),
+        Err(e) => log::debug!("Unable to count peers for metrics: {}", e),
+    }
+    gauges
+}
+
+/// Serves the metrics at `bind` until the plugin exits.  Any path is answered.
+pub async fn serve(bind: &str, metrics: Arc<Metrics>, client: Arc<dyn LightningBackend>, config_holder: Arc<RwLock<Config>>) -> Result<(), Error> {
+    let addr: SocketAddr = bind.parse()?;
+    let make_service = make_service_fn(move |_conn| {
+        let metrics = metrics.clone();
+        let client = client.clone();
+        let config_holder = config_holder.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_req| {
+                let metrics = metrics.clone();
+                let client = client.clone();
+                let config = config_holder.read().unwrap().clone();
+                async move {
+                    let body = metrics.render(&gauges(&*client, &config).await);
+                    Ok::<_, Infallible>(Response::builder()
+                        .header("Content-Type", "text/plain; version=0.0.4")
+                        .body(Body::from(body))
+                        .unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    log::info!("Serving metrics on http://{}", addr);
+    server.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_labels_are_escaped() {
+        let metrics = Metrics::default();
+        metrics.inc("spaz_action_runs_total", &[("action", "say \"hi\"\\\nbye"), ("result", "ok")], 2);
+        let out = metrics.render(&[]);
+        assert!(out.contains("spaz_action_runs_total{action=\"say \\\"hi\\\"\\\\\\nbye\",result=\"ok\"} 2\n"), "{}", out);
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        for ms in [3, 30, 3000] {
+            metrics.observe("spaz_action_duration_seconds", &[("action", "open")], Duration::from_millis(ms));
+        }
+        let out = metrics.render(&[]);
+        let bucket = |le: &str| -> u64 {
+            let prefix = format!("spaz_action_duration_seconds_bucket{{action=\"open\",le=\"{}\"}} ", le);
+            let line = out.lines().find(|l| l.starts_with(&prefix)).unwrap_or_else(|| panic!("no bucket {} in {}", le, out));
+            line[prefix.len()..].parse().unwrap()
+        };
+        assert_eq!(bucket("0.005"), 1);
+        assert_eq!(bucket("0.025"), 1);
+        assert_eq!(bucket("0.05"), 2);
+        assert_eq!(bucket("2.5"), 2);
+        assert_eq!(bucket("5"), 3);
+        assert_eq!(bucket("120"), 3);
+        assert_eq!(bucket("+Inf"), 3);
+        assert!(out.contains("spaz_action_duration_seconds_count{action=\"open\"} 3\n"));
+    }
+}
@@ -0,0 +1,951 @@
+use anyhow::{anyhow, Error, Result};
+use serde_json::json;
+
+use cln_plugin::{options, Builder};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use std::sync::{Arc, RwLock};
+
+use tokio::{task, time};
+use tokio::sync::Notify;
+
+use crate::actions::{ActionContext, ChaosAction, Registry};
+use crate::backend::spawn_backend;
+use crate::breaker::Breakers;
+use crate::budget::Budget;
+use crate::dry_run::DryRunLog;
+use crate::journal::{HistoryFilter, Journal, JournalEntry, JournalResult};
+use crate::metrics::Metrics;
+use crate::replay::{ReplayRequest, Replayer};
+use crate::profiles::{load_profiles, Profiles};
+use crate::rng::SpazRng;
+use crate::run_once::{run_once, RunOnceOutcome, RunOnceRequest};
+use crate::status::Stats;
+use crate::{load_configuration, metrics, Action, Config, ClnClient, LightningBackend};
+
+type Cancellers = Arc<BTreeMap<String, Arc<Notify>>>;
+
+/// Checks the node's network against the config, logging why if it's
+/// refused.  RPC handlers use this rather than `LightningBackend::check_network`,
+/// whose future isn't `Sync`.
+pub async fn check_network(client: &ClnClient, config: &Config) -> Result<(), Error> {
+    if config.dry_run {
+        return Ok(())
+    }
+    let network = client.for_path(&config.rpc_path).network().await?;
+    config.check_network(&network).map_err(|e| {
+        log::warn!("{}", e);
+        e
+    })
+}
+
+pub async fn start_handler(
+    config_holder: Arc<RwLock<Config>>,
+    client: Arc<ClnClient>,
+) -> Result<serde_json::Value, Error> {
+    log::info!("Plugin start requested");
+    let config = config_holder.read().unwrap().clone();
+    check_network(&client, &config).await?;
+    let mut guard = config_holder.write().unwrap();
+    guard.active = true;
+
+    Ok(json!("ok"))
+}
+
+pub async fn stop_handler(
+    config_holder: Arc<RwLock<Config>>,
+    cancellers: Cancellers,
+) -> Result<serde_json::Value, Error> {
+    log::info!("Plugin stop requested");
+    
+    let mut guard = config_holder.write().unwrap();
+    guard.active = false;
+
+    // Abandon anything still in flight, e.g. a slow fundchannel
+    for cancel in cancellers.values() {
+        cancel.notify_waiters();
+    }
+
+    Ok(json!("ok"))
+}
+
+pub async fn config_handler(
+    config_holder: Arc<RwLock<Config>>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let updates = match params {
+        serde_json::Value::Object(m) => m,
+        serde_json::Value::Array(a) => {
+            if a.len() % 2 != 0 {
+                return Err(anyhow!("Expected key/value pairs, got {} arguments", a.len()))
+            }
+            a.chunks(2)
+                .map(|pair| match &pair[0] {
+                    serde_json::Value::String(k) => Ok((k.clone(), pair[1].clone())),
+                    k => Err(anyhow!("Config key must be a string: {}", k)),
+                })
+                .collect::<Result<serde_json::Map<_, _>, Error>>()?
+        }
+        serde_json::Value::Null => serde_json::Map::new(),
+        p => return Err(anyhow!("Unexpected params: {}", p)),
+    };
+
+    let mut guard = config_holder.write().unwrap();
+    if !updates.is_empty() {
+        let updated = guard.with_updates(&updates)?;
+        log::info!("Config updated via RPC: {:?}", updates);
+        *guard = updated;
+    }
+
+    Ok(serde_json::to_value(&*guard)?)
+}
+
+pub async fn profile_handler(
+    config_holder: Arc<RwLock<Config>>,
+    profiles_holder: Arc<RwLock<Profiles>>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let name = match &params {
+        serde_json::Value::Array(a) => a.first().cloned(),
+        serde_json::Value::Object(m) => m.get("profile").cloned(),
+        _ => None,
+    };
+
+    let mut profiles = profiles_holder.write().unwrap();
+    let mut carried_over = vec![];
+    if let Some(name) = name {
+        let name = name.as_str()
+            .ok_or_else(|| anyhow!("Profile name must be a string: {}", name))?
+            .to_string();
+        let mut guard = config_holder.write().unwrap();
+        *guard = profiles.apply(&name, &guard)?;
+        carried_over = profiles.carried_over(&name);
+        log::info!("Switched to profile {}, keeping {:?} from the running config: {:?}", name, carried_over, guard);
+    }
+
+    let mut res = json!({
+        "active": profiles.active,
+        "profiles": profiles.names(),
+    });
+    if !carried_over.is_empty() {
+        res["carried_over"] = json!(carried_over);
+    }
+    Ok(res)
+}
+
+pub async fn actions_handler(
+    config_holder: Arc<RwLock<Config>>,
+    registry: Arc<Registry>,
+    breakers: Arc<Breakers>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let (name, command) = match &params {
+        serde_json::Value::Array(a) => (a.first().cloned(), a.get(1).cloned()),
+        serde_json::Value::Object(m) => (m.get("action").cloned(), m.get("command").cloned()),
+        _ => (None, None),
+    };
+
+    let mut guard = config_holder.write().unwrap();
+    if let Some(name) = name {
+        let name = name.as_str().ok_or_else(|| anyhow!("Action name must be a string: {}", name))?;
+        let action = registry.get(name)?;
+        let enabled = match command.as_ref().and_then(|c| c.as_str()) {
+            Some("enable") => true,
+            Some("disable") => false,
+            c => return Err(anyhow!("Expected enable or disable, got {:?}", c)),
+        };
+        action.set_enabled(&mut guard, enabled);
+        log::info!("Action {} enabled={} via RPC", name, enabled);
+    }
+
+    Ok(json!({
+        "actions": registry.list(&guard),
+        "suspended": breakers.suspended(),
+    }))
+}
+
+pub async fn budget_handler(
+    config_holder: Arc<RwLock<Config>>,
+    budget: Arc<Budget>,
+) -> Result<serde_json::Value, Error> {
+    let config = config_holder.read().unwrap();
+    Ok(json!({ "budgets": budget.report(&config) }))
+}
+
+pub async fn dry_run_handler(
+    config_holder: Arc<RwLock<Config>>,
+    dry_run_log: Arc<DryRunLog>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let toggle = match &params {
+        serde_json::Value::Array(a) => a.first().cloned(),
+        serde_json::Value::Object(m) => m.get("enabled").cloned(),
+        _ => None,
+    };
+
+    let mut guard = config_holder.write().unwrap();
+    if let Some(toggle) = toggle {
+        let enabled = match &toggle {
+            serde_json::Value::Bool(b) => *b,
+            serde_json::Value::String(s) if s == "true" || s == "on" => true,
+            serde_json::Value::String(s) if s == "false" || s == "off" => false,
+            t => return Err(anyhow!("Expected true/false or on/off, got {}", t)),
+        };
+        if enabled && !guard.dry_run {
+            dry_run_log.clear();
+        }
+        guard.dry_run = enabled;
+        log::info!("Dry-run mode set to {} via RPC", enabled);
+    }
+
+    Ok(json!({
+        "dry_run": guard.dry_run,
+        "recorded": dry_run_log.records(),
+    }))
+}
+
+const TARGET_LISTS: [&str; 7] = [
+    "allow_pubkeys",
+    "allow_aliases",
+    "allow_address_types",
+    "deny_pubkeys",
+    "deny_aliases",
+    "deny_address_types",
+    "protected_channels",
+];
+
+pub async fn targets_handler(
+    config_holder: Arc<RwLock<Config>>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let args: Vec<serde_json::Value> = match &params {
+        serde_json::Value::Array(a) => a.clone(),
+        serde_json::Value::Object(m) => ["command", "list", "value"].iter()
+            .filter_map(|k| m.get(*k).cloned())
+            .collect(),
+        _ => vec![],
+    };
+    let args: Vec<&str> = args.iter().filter_map(|a| a.as_str()).collect();
+
+    let mut guard = config_holder.write().unwrap();
+    if let [command, list, value] = args[..] {
+        if !TARGET_LISTS.contains(&list) {
+            return Err(anyhow!("Unknown list {}, expected one of {:?}", list, TARGET_LISTS))
+        }
+        let current = serde_json::to_value(&*guard)?;
+        let mut entries: Vec<String> = serde_json::from_value(current[list].clone())?;
+        match command {
+            "add" if !entries.iter().any(|e| e == value) => entries.push(value.to_string()),
+            "add" => {},
+            "remove" => entries.retain(|e| e != value),
+            c => return Err(anyhow!("Expected add or remove, got {}", c)),
+        }
+        let mut updates = serde_json::Map::new();
+        updates.insert(list.to_string(), json!(entries));
+        *guard = guard.with_updates(&updates)?;
+        log::info!("Target list {} is now {:?}", list, entries);
+    } else if !args.is_empty() {
+        return Err(anyhow!("Usage: spaz-targets [add|remove <list> <value>]"))
+    }
+
+    let current = serde_json::to_value(&*guard)?;
+    Ok(TARGET_LISTS.iter()
+        .map(|l| (l.to_string(), current[*l].clone()))
+        .collect::<serde_json::Map<_, _>>()
+        .into())
+}
+
+/// Runs a single action right away, whether or not spaz is active or the
+/// action enabled, and waits for the outcome
+#[allow(clippy::too_many_arguments)]
+pub async fn run_once_handler(
+    config_holder: Arc<RwLock<Config>>,
+    registry: Arc<Registry>,
+    spaz_rng: Arc<SpazRng>,
+    breakers: Arc<Breakers>,
+    budget: Arc<Budget>,
+    dry_run_log: Arc<DryRunLog>,
+    stats: Arc<Stats>,
+    journal: Arc<Journal>,
+    client: Arc<ClnClient>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let request = RunOnceRequest::from_params(params)?;
+    let name = match request.action.as_str() {
+        "randomize-fee" => Action::Fee.name(),
+        n => n,
+    };
+    let action = registry.get(name)?;
+    let builtin = name.parse::<Action>().ok();
+    if builtin.is_none() && (request.target.is_some() || request.amount.is_some()) {
+        return Err(anyhow!("{} is a custom action and does not take a target or amount", name))
+    }
+    if !request.force && breakers.is_suspended(name) {
+        return Err(anyhow!("{} is suspended by its circuit breaker, pass force to run it anyway", name))
+    }
+
+    let mut c = config_holder.read().unwrap().clone();
+    c.dry_run |= request.dry_run;
+    check_network(&client, &c).await?;
+
+    log::info!("Running {} once: {:?}", name, request);
+    let mut ctx = ActionContext {
+        action: name.to_string(),
+        client: Arc::new(ClnClient {
+            dry_run: c.dry_run.then(|| dry_run_log.clone()),
+            ..client.for_path(&c.rpc_path)
+        }),
+        metrics: client.metrics.clone(),
+        // A stream of its own, so on-demand runs don't shift the scheduled ones
+        rng: spaz_rng.fork(&format!("run-once/{}", name), c.seed),
+        config: c.clone(),
+        breakers,
+        budget,
+        journal,
+    };
+    let timeout = Duration::from_secs_f64(c.timeout_secs);
+    let dry_run = c.dry_run;
+    let name = name.to_string();
+    let started = time::Instant::now();
+    let outcome = spawn_backend(async move {
+        let run = async {
+            match builtin {
+                Some(a) => run_once(&mut ctx, a, &request).await,
+                None => match action.run(&mut ctx).await {
+                    Ok(()) => RunOnceOutcome::new(&name, dry_run),
+                    Err(err) => RunOnceOutcome::new(&name, dry_run).failed(&err),
+                },
+            }
+        };
+        match time::timeout(timeout, run).await {
+            Ok(outcome) => outcome,
+            Err(_) => RunOnceOutcome::new(&name, dry_run).failed(&anyhow!("Timed out after {:?}", timeout)),
+        }
+    }).await?;
+    let result = match &outcome.error {
+        Some(err) => Err(anyhow!("{}", err)),
+        None => Ok(()),
+    };
+    stats.record(&outcome.action, &result);
+    record_run_metrics(&*client, &outcome.action, if result.is_ok() { "ok" } else { "error" }, started);
+    Ok(serde_json::to_value(outcome)?)
+}
+
+pub async fn status_handler(
+    config_holder: Arc<RwLock<Config>>,
+    profiles_holder: Arc<RwLock<Profiles>>,
+    registry: Arc<Registry>,
+    stats: Arc<Stats>,
+) -> Result<serde_json::Value, Error> {
+    let config = config_holder.read().unwrap().clone();
+    let actions: serde_json::Map<String, serde_json::Value> = registry.iter()
+        .map(|a| {
+            let mut status = serde_json::to_value(stats.get(a.name()))?;
+            status["enabled"] = json!(a.enabled(&config));
+            Ok((a.name().to_string(), status))
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(json!({
+        "active": config.active,
+        "dry_run": config.dry_run,
+        "uptime_secs": stats.uptime().as_secs(),
+        "seed": config.seed,
+        "profile": profiles_holder.read().unwrap().active,
+        "actions": actions,
+    }))
+}
+
+pub async fn history_handler(
+    config_holder: Arc<RwLock<Config>>,
+    journal: Arc<Journal>,
+    client: Arc<ClnClient>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let filter = HistoryFilter::from_params(params)?;
+    let config = config_holder.read().unwrap().clone();
+    let client = client.for_path(&config.rpc_path);
+    let entries = spawn_backend(async move { journal.query(&client, &config, &filter).await }).await??;
+    Ok(json!({ "history": entries }))
+}
+
+/// Starts replaying a journal in the background, stops the running replay,
+/// or shows the progress of the latest one
+#[allow(clippy::too_many_arguments)]
+pub async fn replay_handler(
+    config_holder: Arc<RwLock<Config>>,
+    replayer: Arc<Replayer>,
+    spaz_rng: Arc<SpazRng>,
+    budget: Arc<Budget>,
+    dry_run_log: Arc<DryRunLog>,
+    journal: Arc<Journal>,
+    client: Arc<ClnClient>,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let params = match params {
+        serde_json::Value::Array(a) if a.first() == Some(&json!("stop")) => {
+            replayer.stop();
+            return Ok(serde_json::to_value(replayer.progress())?)
+        }
+        serde_json::Value::Array(a) if a.is_empty() => serde_json::Value::Null,
+        serde_json::Value::Array(a) => {
+            let mut m = serde_json::Map::new();
+            let mut args = a.into_iter();
+            if let Some(source) = args.next() {
+                m.insert("source".to_string(), source);
+            }
+            if let Some(speed) = args.next() {
+                let speed = match &speed {
+                    serde_json::Value::String(s) => json!(s.parse::<f64>().map_err(|_| anyhow!("speed must be a number, got {}", s))?),
+                    s => s.clone(),
+                };
+                m.insert("speed".to_string(), speed);
+            }
+            m.into()
+        }
+        p => p,
+    };
+    let request: ReplayRequest = match params {
+        serde_json::Value::Null => return Ok(serde_json::to_value(replayer.progress())?),
+        serde_json::Value::Object(m) if m.is_empty() => return Ok(serde_json::to_value(replayer.progress())?),
+        p => serde_json::from_value(p)?,
+    };
+    if request.speed < 0.0 || !request.speed.is_finite() {
+        return Err(anyhow!("speed must be 0 or more, got {}", request.speed))
+    }
+
+    let mut c = config_holder.read().unwrap().clone();
+    c.dry_run |= request.dry_run;
+    check_network(&client, &c).await?;
+
+    let source = request.source.clone().unwrap_or_else(|| c.journal.clone());
+    let mut source_config = c.clone();
+    source_config.journal = source.clone();
+    let metrics = client.metrics.clone();
+    let client = ClnClient {
+        dry_run: c.dry_run.then(|| dry_run_log.clone()),
+        ..client.for_path(&c.rpc_path)
+    };
+    let (client, entries, mut protected) = spawn_backend(async move {
+        let entries = Journal::default().entries(&client, &source_config).await?;
+        let protected = client.protected_channels().await?;
+        Ok::<_, Error>((client, entries, protected))
+    }).await??;
+    let entries = request.select(entries);
+    protected.extend(c.protected_channels.iter().cloned());
+
+    replayer.begin(&source, c.dry_run, entries.len())?;
+    log::info!("Replaying {} journal entries from {} at {}x", entries.len(), source, request.speed);
+    let ctx = ActionContext {
+        action: "replay".to_string(),
+        client: Arc::new(client),
+        metrics,
+        rng: spaz_rng.fork("replay", c.seed),
+        config: c,
+        // Replays don't consult circuit breakers, so their failures are kept out of the live ones
+        breakers: Arc::new(Breakers::default()),
+        budget,
+        journal,
+    };
+    let background = replayer.clone();
+    task::spawn(async move {
+        background.replay(ctx, entries, &request, &protected).await;
+    });
+    Ok(serde_json::to_value(replayer.progress())?)
+}
+
+/// Runs spaz as a CLN plugin with the actions in `registry`.  Use
+/// `Registry::builtin()` and `Registry::register` to add your own actions.
+pub async fn run(registry: Registry) -> Result<(), Error> {
+    let registry = Arc::new(registry);
+    let config = Config::default();
+    let defaults = Config::default();
+    let config_holder = Arc::new(RwLock::new(config));
+    let start_config_holder = config_holder.clone();
+    let stop_config_holder = config_holder.clone();
+    let cancellers: Cancellers = Arc::new(
+        registry.names().into_iter().map(|a| (a, Arc::new(Notify::new()))).collect()
+    );
+    let stop_cancellers = cancellers.clone();
+    let rpc_config_holder = config_holder.clone();
+    let profile_config_holder = config_holder.clone();
+    let profiles_holder = Arc::new(RwLock::new(Profiles::default()));
+    let rpc_profiles_holder = profiles_holder.clone();
+    let actions_config_holder = config_holder.clone();
+    let actions_registry = registry.clone();
+    let breakers = Arc::new(Breakers::default());
+    let actions_breakers = breakers.clone();
+    let budget = Arc::new(Budget::default());
+    let rpc_budget = budget.clone();
+    let budget_config_holder = config_holder.clone();
+    let dry_run_log = Arc::new(DryRunLog::default());
+    let rpc_dry_run_log = dry_run_log.clone();
+    let dry_run_config_holder = config_holder.clone();
+    let targets_config_holder = config_holder.clone();
+    // Reseeded from the loaded config on first use
+    let spaz_rng = Arc::new(SpazRng::new(defaults.seed));
+    let run_once_config_holder = config_holder.clone();
+    let run_once_registry = registry.clone();
+    let run_once_rng = spaz_rng.clone();
+    let run_once_breakers = breakers.clone();
+    let run_once_budget = budget.clone();
+    let run_once_dry_run_log = dry_run_log.clone();
+    let stats = Arc::new(Stats::default());
+    let run_once_stats = stats.clone();
+    let status_config_holder = config_holder.clone();
+    let status_profiles_holder = profiles_holder.clone();
+    let status_registry = registry.clone();
+    let status_stats = stats.clone();
+    let journal = Arc::new(Journal::default());
+    let run_once_journal = journal.clone();
+    let history_config_holder = config_holder.clone();
+    let history_journal = journal.clone();
+    let metrics = Arc::new(Metrics::default());
+    // One client for the whole plugin, so every caller shares its connections to lightningd
+    let client = Arc::new(ClnClient { metrics: Some(metrics.clone()), ..ClnClient::new(&defaults.rpc_path) });
+    let start_client = client.clone();
+    let run_once_client = client.clone();
+    let history_client = client.clone();
+    let replay_client = client.clone();
+    let replay_config_holder = config_holder.clone();
+    let replayer = Arc::new(Replayer::default());
+    let replay_rng = spaz_rng.clone();
+    let replay_budget = budget.clone();
+    let replay_dry_run_log = dry_run_log.clone();
+    let replay_journal = journal.clone();
+    
+    if let Some(configured) = Builder::new((), tokio::io::stdin(), tokio::io::stdout())
+        .option(options::ConfigOption::new(
+            "spaz-on-load",
+            options::Value::Boolean(false),
+            "Start spazzing on load",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-dry-run",
+            options::Value::Boolean(false),
+            "Log and record mutating calls instead of making them",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-allowed-networks",
+            options::Value::String(defaults.allowed_networks.join(",")),
+            "Comma-separated networks spaz may act on",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-allow-mainnet",
+            options::Value::Boolean(false),
+            "Allow spazzing on mainnet.  You almost certainly don't want this",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-allow-pubkeys",
+            options::Value::String("".to_string()),
+            "Comma-separated node ids to only allow as targets",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-allow-aliases",
+            options::Value::String("".to_string()),
+            "Comma-separated alias globs (* and ?) to only allow as targets",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-allow-address-types",
+            options::Value::String("".to_string()),
+            "Comma-separated address types (ipv4, ipv6, torv3, ...) to only allow as targets",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-deny-pubkeys",
+            options::Value::String("".to_string()),
+            "Comma-separated node ids to never use as targets",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-deny-aliases",
+            options::Value::String("".to_string()),
+            "Comma-separated alias globs (* and ?) to never use as targets; nodes without gossip are never targets while set",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-deny-address-types",
+            options::Value::String("".to_string()),
+            "Comma-separated address types (ipv4, ipv6, torv3, ...) to never use as targets; nodes without gossip are never targets while set",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-protected-channels",
+            options::Value::String("".to_string()),
+            "Comma-separated short channel ids that no action may touch",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-rpc-path",
+            options::Value::String("lightning-rpc".to_string()),
+            "RPC path for talking to your node",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-config-file",
+            options::Value::String("".to_string()),
+            "TOML (or .json) file defining named chaos profiles",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-profile",
+            options::Value::String("".to_string()),
+            "Profile from spaz-config-file to use on startup",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-open-probability",
+            options::Value::String(defaults.open_probability.to_string()),
+            "Per-node probability of opening a channel each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-open-amount-min-sat",
+            options::Value::Integer(defaults.open_amount_min_sat as i64),
+            "Minimum channel size (sat) when opening a channel",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-open-amount-max-sat",
+            options::Value::Integer(defaults.open_amount_max_sat as i64),
+            "Maximum channel size (sat, exclusive) when opening a channel",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-close-probability",
+            options::Value::String(defaults.close_probability.to_string()),
+            "Per-channel probability of closing a channel each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-fee-probability",
+            options::Value::String(defaults.fee_probability.to_string()),
+            "Per-channel probability of randomizing its fee each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-fee-ppm-min",
+            options::Value::Integer(defaults.fee_ppm_min as i64),
+            "Minimum proportional fee (ppm) when randomizing fees",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-fee-ppm-max",
+            options::Value::Integer(defaults.fee_ppm_max as i64),
+            "Maximum proportional fee (ppm, exclusive) when randomizing fees",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-fee-base-min-msat",
+            options::Value::Integer(defaults.fee_base_min_msat as i64),
+            "Minimum base fee (msat) when randomizing fees",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-fee-base-max-msat",
+            options::Value::Integer(defaults.fee_base_max_msat as i64),
+            "Maximum base fee (msat, exclusive) when randomizing fees",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-keysend-probability",
+            options::Value::String(defaults.keysend_probability.to_string()),
+            "Per-node probability of sending a keysend each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-keysend-amount-min-msat",
+            options::Value::Integer(defaults.keysend_amount_min_msat as i64),
+            "Minimum keysend amount (msat)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-keysend-amount-max-msat",
+            options::Value::Integer(defaults.keysend_amount_max_msat as i64),
+            "Maximum keysend amount (msat, exclusive)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-poke-probability",
+            options::Value::String(defaults.poke_probability.to_string()),
+            "Per-node probability of sending an unpayable poke each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-poke-amount-min-msat",
+            options::Value::Integer(defaults.poke_amount_min_msat as i64),
+            "Minimum poke amount (msat)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-poke-amount-max-msat",
+            options::Value::Integer(defaults.poke_amount_max_msat as i64),
+            "Maximum poke amount (msat, exclusive)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-disconnect-probability",
+            options::Value::String(defaults.disconnect_probability.to_string()),
+            "Per-peer probability of force-disconnecting each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-ping-probability",
+            options::Value::String(defaults.ping_probability.to_string()),
+            "Per-peer probability of sending a random-length ping each tick",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-journal",
+            options::Value::String(defaults.journal.clone()),
+            "`datastore` to journal every action to the CLN datastore, the absolute path of a JSONL file, or empty to disable",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-metrics-bind",
+            options::Value::String("".to_string()),
+            "Address to serve Prometheus metrics on, e.g. 127.0.0.1:9750 (disabled if empty)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-seed",
+            options::Value::String("".to_string()),
+            "Seed for all random decisions (random if unset)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-interval",
+            options::Value::String(defaults.interval_secs.to_string()),
+            "Mean number of seconds between runs of each action",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-timeout",
+            options::Value::String(defaults.timeout_secs.to_string()),
+            "Seconds a single run of an action may take before it is abandoned",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-breaker-threshold",
+            options::Value::Integer(defaults.breaker_threshold as i64),
+            "Consecutive failures before an action or target is suspended (0 disables)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-breaker-cooldown",
+            options::Value::String(defaults.breaker_cooldown_secs.to_string()),
+            "Seconds a suspended action or target sits out; doubles on each further failure",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-breaker-max-cooldown",
+            options::Value::String(defaults.breaker_max_cooldown_secs.to_string()),
+            "Upper bound in seconds for the breaker cooldown",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-budget-sent-msat-per-hour",
+            options::Value::Integer(0),
+            "Max msat sent by keysends and pokes in any rolling hour (0 for unlimited)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-budget-sent-msat-per-day",
+            options::Value::Integer(0),
+            "Max msat sent by keysends and pokes in any rolling day (0 for unlimited)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-budget-fees-msat-per-hour",
+            options::Value::Integer(0),
+            "Max msat paid in routing fees in any rolling hour (0 for unlimited)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-budget-fees-msat-per-day",
+            options::Value::Integer(0),
+            "Max msat paid in routing fees in any rolling day (0 for unlimited)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-budget-open-sat-per-hour",
+            options::Value::Integer(0),
+            "Max sat committed to channel opens in any rolling hour (0 for unlimited)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-budget-open-sat-per-day",
+            options::Value::Integer(0),
+            "Max sat committed to channel opens in any rolling day (0 for unlimited)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-schedule",
+            options::Value::String("fixed".to_string()),
+            "fixed (every interval, plus jitter) or poisson (exponential inter-arrival times)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-jitter",
+            options::Value::String(defaults.jitter.to_string()),
+            "Fraction of the interval to randomly add or remove with the fixed schedule",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-open-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between open runs (0 uses spaz-interval)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-close-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between close runs (0 uses spaz-interval)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-fee-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between fee runs (0 uses spaz-interval)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-keysend-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between keysend runs (0 uses spaz-interval)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-poke-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between poke runs (0 uses spaz-interval)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-disconnect-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between disconnect runs (0 uses spaz-interval)",
+        ))
+        .option(options::ConfigOption::new(
+            "spaz-ping-interval",
+            options::Value::String("0".to_string()),
+            "Mean seconds between ping runs (0 uses spaz-interval)",
+        ))
+        .rpcmethod("start-spazzing", "enables this plugn", move |_p,_v| { start_handler(start_config_holder.clone(), start_client.clone()) } )
+        .rpcmethod("stop-spazzing", "disables this plugn", move |_p,_v| { stop_handler(stop_config_holder.clone(), stop_cancellers.clone()) } )
+        .rpcmethod("spaz-config", "show config, or update it with key/value pairs", move |_p, v| { config_handler(rpc_config_holder.clone(), v) } )
+        .rpcmethod("spaz-profile", "list profiles, or switch to the named profile", move |_p, v| { profile_handler(profile_config_holder.clone(), rpc_profiles_holder.clone(), v) } )
+        .rpcmethod("spaz-budget", "show spend in the last hour and day, and what is left of each budget", move |_p, _v| { budget_handler(budget_config_holder.clone(), rpc_budget.clone()) } )
+        .rpcmethod("spaz-dry-run", "turn dry-run mode on or off, and show the calls it skipped", move |_p, v| { dry_run_handler(dry_run_config_holder.clone(), rpc_dry_run_log.clone(), v) } )
+        .rpcmethod("spaz-targets", "show target allow/deny lists and protected channels, or change one: spaz-targets add|remove <list> <value>", move |_p, v| { targets_handler(targets_config_holder.clone(), v) } )
+        .rpcmethod("spaz-run-once", "run one action now and return its outcome: spaz-run-once <action> [target] [amount]", move |_p, v| {
+            run_once_handler(run_once_config_holder.clone(), run_once_registry.clone(), run_once_rng.clone(), run_once_breakers.clone(), run_once_budget.clone(), run_once_dry_run_log.clone(), run_once_stats.clone(), run_once_journal.clone(), run_once_client.clone(), v)
+        })
+        .rpcmethod("spaz-status", "show whether spaz is active, and counters, last error and next run for each action", move |_p, _v| {
+            status_handler(status_config_holder.clone(), status_profiles_holder.clone(), status_registry.clone(), status_stats.clone())
+        })
+        .rpcmethod("spaz-history", "show journal entries, filtered by action, target, since, until (unix seconds), result and limit", move |_p, v| {
+            history_handler(history_config_holder.clone(), history_journal.clone(), history_client.clone(), v)
+        })
+        .rpcmethod("spaz-replay", "replay a journal with its original timing: spaz-replay [source] [speed], or spaz-replay stop", move |_p, v| {
+            replay_handler(replay_config_holder.clone(), replayer.clone(), replay_rng.clone(), replay_budget.clone(), replay_dry_run_log.clone(), replay_journal.clone(), replay_client.clone(), v)
+        })
+        .rpcmethod("spaz-actions", "list actions, or enable/disable one: spaz-actions <name> enable|disable", move |_p, v| { actions_handler(actions_config_holder.clone(), actions_registry.clone(), actions_breakers.clone(), v) } )
+
+        .configure()
+        .await?
+    {
+        if let Err(e) = load_configuration(&configured, config_holder.clone()) {
+            log::error!("Invalid configuration, disabling spaz: {:#}", e);
+            return configured.disable(&format!("invalid configuration: {:#}", e)).await
+        }
+        let plugin = configured.start().await?;
+        let profiles = match load_profiles(&plugin, config_holder.clone()) {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                log::error!("Couldn't load profiles, starting without any: {:#}", e);
+                Profiles { base: config_holder.read().unwrap().clone(), ..Default::default() }
+            }
+        };
+        *profiles_holder.write().unwrap() = profiles;
+
+        let config = config_holder.read().unwrap().clone();
+        match check_network(&client, &config).await {
+            Ok(_) => log::info!("Network check passed"),
+            Err(e) => log::warn!("Mutating actions will be refused until this is resolved: {}", e),
+        }
+
+        if !config.metrics_bind.is_empty() {
+            let (bind, metrics, client, config_holder) = (config.metrics_bind.clone(), metrics.clone(), client.clone(), config_holder.clone());
+            task::spawn(async move {
+                if let Err(e) = metrics::serve(&bind, metrics, client, config_holder).await {
+                    log::error!("Metrics endpoint on {} failed: {}", bind, e);
+                }
+            });
+        }
+
+        for action in registry.iter() {
+            spawn_action(action.clone(), config_holder.clone(), spaz_rng.clone(), breakers.clone(), budget.clone(), dry_run_log.clone(), stats.clone(), journal.clone(), client.clone(), cancellers[action.name()].clone());
+        }
+        plugin.join().await
+    } else {
+        Ok(())
+    }
+}
+
+fn record_run_metrics(client: &dyn LightningBackend, action: &str, result: &str, started: time::Instant) {
+    if let Some(metrics) = client.metrics() {
+        metrics.inc("spaz_action_runs_total", &[("action", action), ("result", result)], 1);
+        metrics.observe("spaz_action_duration_seconds", &[("action", action)], started.elapsed());
+    }
+}
+
+/// Runs `action` forever on its own schedule.  Each run is bounded by
+/// `timeout_secs` and can be cancelled through `cancel`, so a hung RPC in
+/// one action never holds up the others.  Runs are skipped while the
+/// action's circuit breaker is open.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_action(
+    action: Arc<dyn ChaosAction>,
+    config_holder: Arc<RwLock<Config>>,
+    spaz_rng: Arc<SpazRng>,
+    breakers: Arc<Breakers>,
+    budget: Arc<Budget>,
+    dry_run_log: Arc<DryRunLog>,
+    stats: Arc<Stats>,
+    journal: Arc<Journal>,
+    client: Arc<dyn LightningBackend>,
+    cancel: Arc<Notify>,
+) -> task::JoinHandle<()> {
+    task::spawn(async move {
+        loop {
+            let delay = {
+                let c = config_holder.read().unwrap();
+                c.schedule.next_delay(action.interval_secs(&c), c.jitter, &mut spaz_rng.fork_schedule(action.name(), c.seed))
+            };
+            stats.scheduled(action.name(), delay);
+            time::sleep(delay).await;
+
+            let c = config_holder.read().unwrap().clone();
+            if !c.active || !action.enabled(&c) {
+                continue
+            }
+            let name = action.name().to_string();
+            // The daemon drives several nodes from one process, so say which
+            let run_name = match journal.node() {
+                Some(node) => format!("{} on {}", name, node),
+                None => name.clone(),
+            };
+            if breakers.is_suspended(&name) {
+                log::debug!("Skipping suspended action {}", run_name);
+                continue
+            }
+            log::debug!("Spazzing {}", run_name);
+
+            if let Err(err) = client.check_network(&c).await {
+                stats.record_skipped(&name, &err);
+                if let Some(metrics) = client.metrics() {
+                    metrics.inc("spaz_action_runs_total", &[("action", &name), ("result", "skipped")], 1);
+                }
+                let mut entry = JournalEntry::new(&name, None, JournalResult::Skipped);
+                entry.error = Some(format!("{:#}", err));
+                entry.dry_run = c.dry_run;
+                journal.append(client.clone(), &c, entry);
+                continue
+            }
+
+            let timeout = Duration::from_secs_f64(c.timeout_secs);
+            let mut ctx = ActionContext {
+                action: name.clone(),
+                client: client.for_run(&c, c.dry_run.then(|| dry_run_log.clone())),
+                metrics: client.metrics(),
+                rng: spaz_rng.fork(&name, c.seed),
+                config: c.clone(),
+                breakers: breakers.clone(),
+                budget: budget.clone(),
+                journal: journal.clone(),
+            };
+            let started = time::Instant::now();
+            tokio::select! {
+                res = time::timeout(timeout, action.run(&mut ctx)) => {
+                    let res = res.unwrap_or_else(|_| Err(anyhow!("Timed out after {:?}", timeout)));
+                    stats.record(&name, &res);
+                    record_run_metrics(&*client, &name, if res.is_ok() { "ok" } else { "error" }, started);
+                    match res {
+                        Ok(_) => {
+                            log::debug!("Finished {}", run_name);
+                            breakers.record_success(&name);
+                        }
+                        Err(err) => {
+                            log::warn!("Error running {}.  Continuing: {:?}", run_name, err);
+                            breakers.record_failure(&name, &c);
+                            let mut entry = JournalEntry::new(&name, None, JournalResult::Error);
+                            entry.error = Some(format!("{:#}", err));
+                            entry.dry_run = c.dry_run;
+                            journal.append(ctx.client.clone(), &c, entry);
+                        }
+                    }
+                },
+                _ = cancel.notified() => {
+                    log::info!("Cancelled in-flight {}", run_name);
+                    stats.record_cancelled(&name);
+                    record_run_metrics(&*client, &name, "cancelled", started);
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,193 @@
+use anyhow::{anyhow, Error, Result};
+use cln_plugin::options;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use crate::{Config, PluginOptions};
+
+/// A profile is a set of `Config` field overrides, keyed by field name.
+pub type Profile = serde_json::Map<String, serde_json::Value>;
+
+/// Safety settings a profile switch keeps from the running config unless the
+/// profile sets them, so target lists and the network guard tightened over
+/// `spaz-config` aren't undone by switching profiles.
+pub const KEPT_ACROSS_PROFILES: [&str; 8] = [
+    "allowed_networks",
+    "allow_pubkeys",
+    "allow_aliases",
+    "allow_address_types",
+    "deny_pubkeys",
+    "deny_aliases",
+    "deny_address_types",
+    "protected_channels",
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// Named profiles loaded from `spaz-config-file`.  Profiles are always
+/// applied on top of `base` (the config built from plugin options), so
+/// switching from one profile to another doesn't leak settings between them.
+/// The exceptions are the `KEPT_ACROSS_PROFILES` settings and the
+/// active and dry-run flags, which come from the running config.
+#[derive(Debug, Default)]
+pub struct Profiles {
+    pub base: Config,
+    pub profiles: BTreeMap<String, Profile>,
+    pub active: Option<String>,
+}
+
+impl Profiles {
+    /// Reads a TOML file, or JSON if the extension is `.json`.
+    pub fn load(path: &Path, base: Config) -> Result<Profiles, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read config file {}: {}", path.to_string_lossy(), e))?;
+        let file: ProfileFile = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => toml::from_str(&contents)?,
+        };
+
+        for (name, profile) in &file.profiles {
+            base.with_updates(profile)
+                .map_err(|e| anyhow!("Invalid profile {}: {}", name, e))?;
+        }
+
+        Ok(Profiles { base, profiles: file.profiles, active: None })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    /// The `KEPT_ACROSS_PROFILES` settings that switching to `name` takes
+    /// from the running config rather than from `base`
+    pub fn carried_over(&self, name: &str) -> Vec<&'static str> {
+        let profile = self.profiles.get(name);
+        KEPT_ACROSS_PROFILES.into_iter()
+            .filter(|key| !profile.map(|p| p.contains_key(*key)).unwrap_or(false))
+            .collect()
+    }
+
+    /// Builds the config for profile `name`.  `active` is carried over from
+    /// `current`, the running config, since choosing a profile shouldn't start
+    /// or stop spazzing.  So is dry-run mode: a profile may turn it on, but
+    /// only `spaz-dry-run off` takes a node live.
+    pub fn apply(&mut self, name: &str, current: &Config) -> Result<Config, Error> {
+        let mut updates = self.profiles.get(name)
+            .ok_or_else(|| anyhow!("Unknown profile: {}.  Available: {:?}", name, self.names()))?
+            .clone();
+        let running = serde_json::to_value(current)?;
+        for key in self.carried_over(name) {
+            updates.insert(key.to_string(), running[key].clone());
+        }
+        let mut config = self.base.with_updates(&updates)?;
+        config.active = current.active;
+        config.dry_run |= current.dry_run;
+        self.active = Some(name.to_string());
+        Ok(config)
+    }
+}
+
+pub fn load_profiles(plugin: &impl PluginOptions, config_holder: Arc<RwLock<Config>>) -> Result<Profiles, Error> {
+    let base = config_holder.read().unwrap().clone();
+
+    let mut profiles = match plugin.option("spaz-config-file") {
+        Some(options::Value::String(s)) if s.is_empty() => {
+            log::debug!("No 'spaz-config-file' set.  No profiles available.");
+            return Ok(Profiles { base, ..Default::default() })
+        }
+        Some(options::Value::String(s)) => Profiles::load(Path::new(&s), base)?,
+        None => {
+            log::info!("Missing 'spaz-config-file' option.  No profiles available.");
+            return Ok(Profiles { base, ..Default::default() })
+        }
+        Some(o) => return Err(anyhow!("spaz-config-file is not a valid path: {:?}.", o)),
+    };
+    log::info!("Loaded profiles: {:?}", profiles.names());
+
+    match plugin.option("spaz-profile") {
+        Some(options::Value::String(s)) if s.is_empty() => {}
+        Some(options::Value::String(s)) => {
+            let mut c = config_holder.write().unwrap();
+            *c = profiles.apply(&s, &c)?;
+            log::info!("Using profile {}: {:?}", s, c);
+        }
+        None => {}
+        Some(o) => return Err(anyhow!("spaz-profile is not a valid profile name: {:?}.", o)),
+    }
+
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles() -> Profiles {
+        let file: ProfileFile = toml::from_str(r#"
+            [profiles.quiet]
+            interval_secs = 60.0
+
+            [profiles.careful]
+            dry_run = true
+        "#).unwrap();
+        Profiles { base: Config::default(), profiles: file.profiles, active: None }
+    }
+
+    #[test]
+    fn switching_profiles_keeps_dry_run_on() {
+        let mut profiles = profiles();
+        let current = Config { dry_run: true, active: false, ..Config::default() };
+        let config = profiles.apply("quiet", &current).unwrap();
+        assert!(config.dry_run);
+        assert!(!config.active);
+        assert_eq!(config.interval_secs, 60.0);
+        assert_eq!(profiles.active.as_deref(), Some("quiet"));
+    }
+
+    #[test]
+    fn profiles_can_turn_dry_run_on_but_not_off() {
+        let mut profiles = profiles();
+        let live = Config::default();
+        assert!(profiles.apply("careful", &live).unwrap().dry_run);
+        assert!(!profiles.apply("quiet", &live).unwrap().dry_run);
+        assert!(profiles.apply("unknown", &live).is_err());
+    }
+
+    #[test]
+    fn switching_profiles_keeps_target_lists_and_network_guard() {
+        let file: ProfileFile = toml::from_str(r#"
+            [profiles.quiet]
+            interval_secs = 60.0
+
+            [profiles.open]
+            deny_pubkeys = []
+        "#).unwrap();
+        let mut profiles = Profiles { base: Config::default(), profiles: file.profiles, active: None };
+        let current = Config {
+            deny_pubkeys: vec!["02aa".to_string()],
+            allowed_networks: vec!["regtest".to_string()],
+            protected_channels: vec!["103x1x0".to_string()],
+            interval_secs: 1.0,
+            ..Config::default()
+        };
+        let config = profiles.apply("quiet", &current).unwrap();
+        assert_eq!(config.deny_pubkeys, current.deny_pubkeys);
+        assert_eq!(config.allowed_networks, current.allowed_networks);
+        assert_eq!(config.protected_channels, current.protected_channels);
+        // Everything else still comes from the base and the profile
+        assert_eq!(config.interval_secs, 60.0);
+
+        // A profile that sets a list replaces it
+        let config = profiles.apply("open", &current).unwrap();
+        assert!(config.deny_pubkeys.is_empty());
+        assert!(!profiles.carried_over("open").contains(&"deny_pubkeys"));
+        assert!(profiles.carried_over("open").contains(&"allowed_networks"));
+    }
+}
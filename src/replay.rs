@@ -0,0 +1,258 @@
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{self, Instant};
+
+use crate::actions::ActionContext;
+use crate::budget::Spend;
+use crate::filter::TargetFilter;
+use crate::journal::{JournalEntry, JournalResult};
+use crate::{Action, Amount};
+
+/// Parameters to `spaz-replay`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplayRequest {
+    /// A JSONL journal or `datastore`.  Defaults to the current `journal`.
+    pub source: Option<String>,
+    /// How many times faster than the original to replay.  0 replays without delays.
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+    /// Targets (pubkeys or short channel ids) in the journal to replace with targets on this node
+    #[serde(default)]
+    pub map: BTreeMap<String, String>,
+    /// Only replay this action
+    pub action: Option<String>,
+    /// Unix timestamps in seconds, inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Record the calls instead of making them, even if dry-run mode is off
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+impl ReplayRequest {
+    /// The journal entries that were actually sent to lightningd, in the order they happened
+    pub fn select(&self, entries: Vec<JournalEntry>) -> Vec<JournalEntry> {
+        entries.into_iter()
+            .filter(|e| e.result != JournalResult::Skipped && e.target.is_some())
+            .filter(|e| self.action.as_ref().map(|a| *a == e.action).unwrap_or(true))
+            .filter(|e| self.since.map(|s| e.timestamp / 1000 >= s).unwrap_or(true))
+            .filter(|e| self.until.map(|u| e.timestamp / 1000 <= u).unwrap_or(true))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReplayProgress {
+    pub running: bool,
+    pub source: String,
+    pub dry_run: bool,
+    pub total: usize,
+    pub issued: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Runs one replay at a time and keeps the progress of the latest one
+#[derive(Default)]
+pub struct Replayer {
+    progress: Mutex<ReplayProgress>,
+    stopping: AtomicBool,
+    /// Wakes a replay waiting for its next entry
+    cancel: Notify,
+}
+
+enum Replayed {
+    Issued,
+    Skipped(String),
+}
+
+impl Replayer {
+    pub fn progress(&self) -> ReplayProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.cancel.notify_waiters();
+    }
+
+    /// Marks a replay as started, failing if one is already running
+    pub fn begin(&self, source: &str, dry_run: bool, total: usize) -> Result<(), Error> {
+        let mut progress = self.progress.lock().unwrap();
+        if progress.running {
+            return Err(anyhow!("A replay is already running, stop it first"))
+        }
+        self.stopping.store(false, Ordering::SeqCst);
+        *progress = ReplayProgress { running: true, source: source.to_string(), dry_run, total, ..Default::default() };
+        Ok(())
+    }
+
+    /// Re-issues `entries` with their original spacing, divided by `speed`,
+    /// through `ctx` as if each were a run of its action.  Channels in
+    /// `protected` are left alone.  Call `begin` first.
+    pub async fn replay(&self, mut ctx: ActionContext, entries: Vec<JournalEntry>, request: &ReplayRequest, protected: &[String]) {
+        let started = Instant::now();
+        let first = entries.first().map(|e| e.timestamp).unwrap_or(0);
+        for entry in entries {
+            if self.stopping.load(Ordering::SeqCst) {
+                log::info!("Replay stopped");
+                break
+            }
+            if request.speed > 0.0 {
+                let due = Duration::from_millis(entry.timestamp.saturating_sub(first)).div_f64(request.speed);
+                tokio::select! {
+                    _ = time::sleep_until(started + due) => {},
+                    _ = self.cancel.notified() => {
+                        log::info!("Replay stopped");
+                        break
+                    }
+                }
+            }
+            let res = replay_entry(&mut ctx, &entry, &request.map, protected).await;
+            let mut progress = self.progress.lock().unwrap();
+            match res {
+                Ok(Replayed::Issued) => progress.issued += 1,
+                Ok(Replayed::Skipped(reason)) => {
+                    log::info!("Not replaying {} on {:?}: {}", entry.action, entry.target, reason);
+                    progress.skipped += 1;
+                }
+                Err(e) => {
+                    log::warn!("Replaying {} on {:?} failed: {:#}", entry.action, entry.target, e);
+                    progress.failed += 1;
+                    progress.last_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        let mut progress = self.progress.lock().unwrap();
+        progress.running = false;
+        log::info!("Replay finished: {:?}", progress);
+    }
+}
+
+/// Re-issues one entry through `ctx`, which is where its target is checked
+/// against the allow/deny lists and budgets and its outcome is journaled,
+/// the same as for a scheduled run
+async fn replay_entry(
+    ctx: &mut ActionContext,
+    entry: &JournalEntry,
+    map: &BTreeMap<String, String>,
+    protected: &[String],
+) -> Result<Replayed, Error> {
+    let action: Action = match entry.action.parse() {
+        Ok(a) => a,
+        Err(_) => return Ok(Replayed::Skipped("not a built-in action".to_string())),
+    };
+    ctx.action = action.name().to_string();
+    let original = entry.target.as_deref().unwrap_or_default();
+    let target = map.get(original).map(|t| t.as_str()).unwrap_or(original).to_string();
+    let amount = || entry.amount.ok_or_else(|| anyhow!("Journal entry has no amount"));
+    let client = ctx.client.clone();
+    let filter_config = ctx.config.clone();
+    let filter = TargetFilter::new(&filter_config);
+
+    match action {
+        Action::Keysend | Action::Poke | Action::Open => {
+            let node = match client.list_nodes().await?.into_iter().find(|n| n.nodeid.to_string() == target) {
+                Some(node) => node,
+                None => return Ok(Replayed::Skipped(format!("no node {} in the graph", target))),
+            };
+            if !filter.allows(&target, Some(&node)) {
+                return Ok(Replayed::Skipped(format!("{} is not an allowed target", target)))
+            }
+            let amount = amount()?;
+            let amounts = match action {
+                Action::Open => vec![(Spend::OpenSat, amount)],
+                _ => ActionContext::payment_amounts(amount).to_vec(),
+            };
+            let reservation = match ctx.reserve(&amounts, &target) {
+                Some(reservation) => reservation,
+                None => return Ok(Replayed::Skipped("budget exhausted".to_string())),
+            };
+            match action {
+                Action::Keysend => {
+                    let res = client.keysend_node(node.nodeid, Amount::from_msat(amount)).await;
+                    let params = res.as_ref().map(|p| json!({ "payment_hash": p.payment_hash.to_string() })).unwrap_or_default();
+                    ctx.record_target(&target, Some(amount), params, &res);
+                    let payment = res?;
+                    let sent = payment.amount_sent_msat.msat();
+                    ctx.settle(reservation, &[(Spend::SentMsat, sent), (Spend::FeesMsat, sent.saturating_sub(payment.amount_msat.msat()))]);
+                }
+                Action::Poke => {
+                    let res = client.poke_node(node.nodeid, amount, &mut ctx.rng).await;
+                    ctx.record_target(&target, Some(amount), serde_json::Value::Null, &res);
+                    let fees_msat = res?;
+                    ctx.settle(reservation, &[(Spend::FeesMsat, fees_msat)]);
+                }
+                _ => {
+                    let res = client.open_channel_to_node(node, amount).await;
+                    let params = res.as_ref().map(|txid| json!({ "txid": txid })).unwrap_or_default();
+                    ctx.record_target(&target, Some(amount), params, &res);
+                    res?;
+                    ctx.settle(reservation, &[(Spend::OpenSat, amount)]);
+                }
+            }
+        }
+        Action::Disconnect | Action::Ping => {
+            let peer = match client.list_peers().await?.into_iter().find(|p| p.connected && p.id.to_string() == target) {
+                Some(peer) => peer,
+                None => return Ok(Replayed::Skipped(format!("not connected to {}", target))),
+            };
+            if !filter.allows(&target, ctx.gossip(&filter).await?.get(&target)) {
+                return Ok(Replayed::Skipped(format!("{} is not an allowed target", target)))
+            }
+            let res = match action {
+                Action::Disconnect => client.disconnect_peer(peer.id).await,
+                _ => client.random_ping_peer(peer.id, &mut ctx.rng).await,
+            };
+            ctx.record_target(&target, None, serde_json::Value::Null, &res);
+            res?;
+        }
+        Action::Close | Action::Fee => {
+            let channel = match client.list_channels().await?.into_iter().find(|c| c.short_channel_id.as_deref() == Some(&target)) {
+                Some(channel) => channel,
+                None => return Ok(Replayed::Skipped(format!("no channel {}", target))),
+            };
+            if protected.contains(&target) {
+                return Ok(Replayed::Skipped(format!("{} is protected", target)))
+            }
+            if !filter.allows(&channel.peer_id, ctx.gossip(&filter).await?.get(&channel.peer_id)) {
+                return Ok(Replayed::Skipped(format!("{} is with a peer that is not an allowed target", target)))
+            }
+            match action {
+                Action::Close => {
+                    let res = client.close_channel(&target).await;
+                    let params = res.as_ref().map(|txid| json!({ "txid": txid })).unwrap_or_default();
+                    ctx.record_target(&target, None, params, &res);
+                    res?;
+                    ctx.record_close();
+                }
+                _ => {
+                    let ppm = entry.params["fee_ppm"].as_u64();
+                    let base = entry.params["fee_base_msat"].as_u64();
+                    let (ppm, base) = match (ppm, base) {
+                        (Some(ppm), Some(base)) => (ppm, base),
+                        _ => return Ok(Replayed::Skipped("journal entry has no fee".to_string())),
+                    };
+                    let res = client.randomize_fee(&target, (ppm, ppm), (base, base), &mut ctx.rng).await;
+                    let params = res.as_ref().map(|(ppm, base)| json!({ "fee_ppm": ppm, "fee_base_msat": base })).unwrap_or_default();
+                    ctx.record_target(&target, None, params, &res);
+                    res?;
+                }
+            }
+        }
+    }
+    Ok(Replayed::Issued)
+}
@@ -0,0 +1,52 @@
+use anyhow::{anyhow, Error, Result};
+use serde::de::DeserializeOwned;
+use std::time::Instant;
+
+use crate::grpc;
+use crate::metrics::Metrics;
+
+/// An HTTP client for a node's REST interface.  With `cert`, the server
+/// must present a certificate issued by it, or the certificate itself if
+/// it's self-signed, under whatever hostname the node is reached by: nodes
+/// issue these certificates for themselves, for names like `localhost` that
+/// rarely match the one in a docker-compose network.
+pub fn client(cert: Option<&str>, what: &str) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(path) = cert {
+        let pem = grpc::read_file(path, what)?;
+        builder = builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(reqwest::Certificate::from_pem(&pem)?)
+            .danger_accept_invalid_hostnames(true);
+    }
+    Ok(builder.build()?)
+}
+
+/// Sends `request`, recording its latency under `method`.  A non-success
+/// status is an error with the body's `message`, which CLNRest and LND's
+/// REST proxy both send.  Latency is measured to the response headers.
+pub async fn call(request: reqwest::RequestBuilder, method: &str, metrics: Option<&Metrics>) -> Result<reqwest::Response, Error> {
+    let started = Instant::now();
+    let res = request.send().await;
+    if let Some(metrics) = metrics {
+        metrics.observe("spaz_rpc_latency_seconds", &[("method", method)], started.elapsed());
+    }
+    let res = res.map_err(|e| anyhow!("Error calling {}: {}", method, e))?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<serde_json::Value>(&body).ok()
+            .and_then(|v| v["message"].as_str().map(|m| m.to_string()))
+            .unwrap_or(body);
+        return Err(anyhow!("Error calling {} ({}): {}", method, status, message))
+    }
+    Ok(res)
+}
+
+/// Like `call`, deserializing the JSON body
+pub async fn send<T: DeserializeOwned>(request: reqwest::RequestBuilder, method: &str, metrics: Option<&Metrics>) -> Result<T, Error> {
+    let body: serde_json::Value = call(request, method, metrics).await?.json().await
+        .map_err(|e| anyhow!("Malformed response to {}: {}", method, e))?;
+    log::trace!("{} response: {}", method, body);
+    serde_json::from_value(body).map_err(|e| anyhow!("Unexpected response to {}: {}", method, e))
+}
@@ -0,0 +1,115 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::Action;
+
+/// The single source of randomness for spaz.
+///
+/// Each action draws from its own stream derived from the seed, so the
+/// decisions one action makes don't depend on how often the others ran.
+/// Every run of an action gets a child RNG forked from its stream; the
+/// child is owned, so it can be held across `.await`s.
+pub struct SpazRng {
+    inner: Mutex<Streams>,
+}
+
+struct Streams {
+    seed: u64,
+    streams: BTreeMap<String, StdRng>,
+}
+
+impl SpazRng {
+    pub fn new(seed: u64) -> SpazRng {
+        SpazRng { inner: Mutex::new(Streams { seed, streams: BTreeMap::new() }) }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.inner.lock().unwrap().seed
+    }
+
+    /// Returns an RNG for the next run of `action`.  If `seed` differs from
+    /// the current seed (e.g. it was changed over RPC) all streams restart from it.
+    pub fn fork(&self, action: &str, seed: u64) -> StdRng {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.seed != seed {
+            log::info!("Reseeding RNG with seed {}", seed);
+            inner.seed = seed;
+            inner.streams.clear();
+        }
+        let stream = inner.streams
+            .entry(action.to_string())
+            .or_insert_with(|| StdRng::seed_from_u64(stream_seed(seed, action)));
+        StdRng::seed_from_u64(stream.gen())
+    }
+
+    /// Returns an RNG for the delay before the next run of `action`.  Delays
+    /// come from a stream of their own, so how many were drawn never shifts
+    /// the choices runs make.
+    pub fn fork_schedule(&self, action: &str, seed: u64) -> StdRng {
+        self.fork(&format!("{}/schedule", action), seed)
+    }
+}
+
+/// Built-in actions mix their position in `Action::ALL` into the seed, as
+/// they always have, so a seed reproduces the same run across releases.
+/// Custom actions have no position and mix in their name with FNV-1a,
+/// which unlike `DefaultHasher` is guaranteed to be stable across Rust releases.
+fn stream_seed(seed: u64, action: &str) -> u64 {
+    if let Some(index) = Action::ALL.iter().position(|a| a.name() == action) {
+        return seed ^ (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    }
+    let hash = action.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    seed ^ hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_reproduce_the_same_draws() {
+        // Pinned: these change only if a seed stops reproducing earlier runs
+        let rng = SpazRng::new(42);
+        assert_eq!(rng.fork("keysend", 42).gen::<u64>(), 5_243_066_954_846_897_503);
+        assert_eq!(rng.fork("keysend", 42).gen::<u64>(), 16_309_659_159_978_837_011);
+        assert_eq!(rng.fork("fee", 42).gen::<u64>(), 4_719_289_041_585_470_605);
+        assert_eq!(rng.fork("my-custom-action", 42).gen::<u64>(), 11_731_338_777_228_690_261);
+    }
+
+    #[test]
+    fn streams_are_independent() {
+        let a = SpazRng::new(7);
+        let b = SpazRng::new(7);
+        a.fork("open", 7);
+        a.fork("open", 7);
+        assert_eq!(a.fork("ping", 7).gen::<u64>(), b.fork("ping", 7).gen::<u64>());
+        assert_ne!(SpazRng::new(7).fork("open", 7).gen::<u64>(), SpazRng::new(7).fork("close", 7).gen::<u64>());
+    }
+
+    #[test]
+    fn schedule_draws_leave_runs_alone() {
+        // Pinned, as in seeds_reproduce_the_same_draws, however many delays come first
+        for delays in [0, 1, 5] {
+            let rng = SpazRng::new(42);
+            for _ in 0..delays {
+                rng.fork_schedule("keysend", 42).gen::<u64>();
+            }
+            assert_eq!(rng.fork("keysend", 42).gen::<u64>(), 5_243_066_954_846_897_503);
+            assert_eq!(rng.fork("keysend", 42).gen::<u64>(), 16_309_659_159_978_837_011);
+        }
+        assert_ne!(SpazRng::new(42).fork_schedule("keysend", 42).gen::<u64>(), 5_243_066_954_846_897_503);
+    }
+
+    #[test]
+    fn reseeding_restarts_streams() {
+        let rng = SpazRng::new(1);
+        let first = rng.fork("poke", 1).gen::<u64>();
+        rng.fork("poke", 2);
+        assert_eq!(rng.fork("poke", 1).gen::<u64>(), first);
+        assert_eq!(rng.seed(), 1);
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Error, Result};
+use cln_rpc::{ClnRpc, Request, Response, RpcError};
+use serde_json::json;
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// How many idle connections of each kind to keep around.  Callers beyond
+/// this get a connection of their own that is closed after the call.
+const MAX_IDLE: usize = 4;
+
+/// Idle connections of one kind, with the socket each is connected to
+struct Idle<T>(Mutex<Vec<(String, T)>>);
+
+impl<T> Default for Idle<T> {
+    fn default() -> Idle<T> {
+        Idle(Mutex::new(vec![]))
+    }
+}
+
+impl<T> Idle<T> {
+    fn take(&self, path: &str) -> Option<T> {
+        let mut idle = self.0.lock().unwrap();
+        // Drop connections to a socket we no longer use, e.g. after `rpc_path` changed
+        idle.retain(|(p, _)| p == path);
+        idle.pop().map(|(_, conn)| conn)
+    }
+
+    fn put(&self, path: &str, conn: T) {
+        let mut idle = self.0.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push((path.to_string(), conn));
+        }
+    }
+}
+
+/// Long-lived connections to lightningd's RPC socket, shared by every
+/// `ClnClient` cloned from the same client.  Each call borrows a connection
+/// for its duration, so concurrent callers never interleave on a socket.
+#[derive(Default)]
+pub struct RpcPool {
+    idle: Idle<ClnRpc>,
+    /// For methods outside `cln_rpc`'s model, see `call_raw`
+    raw: Idle<UnixStream>,
+}
+
+impl RpcPool {
+
+    async fn connect(path: &str) -> Result<ClnRpc, Error> {
+        ClnRpc::new(Path::new(path)).await.map_err(|e| {
+            log::error!("Error initializing CLN RPC - does path {} exist {}", path, e);
+            e
+        })
+    }
+
+    /// Sends `request` over an idle connection, or a new one if there is
+    /// none.  If lightningd has closed a pooled connection since it was last
+    /// used (e.g. it restarted), the call is retried once on a fresh one as
+    /// long as that can't repeat a side effect: always when writing failed,
+    /// and for read-only requests when reading the response failed.  Any
+    /// failure without an error code from lightningd may leave the socket
+    /// mid-response, so the connection is dropped rather than reused.
+    pub async fn call(&self, path: &str, request: Request) -> Result<Response, Error> {
+        let (mut rpc, pooled) = match self.idle.take(path) {
+            Some(rpc) => (rpc, true),
+            None => (Self::connect(path).await?, false),
+        };
+        let mut res = rpc.call(request.clone()).await;
+        if let Err(e) = &res {
+            if pooled && stale(e, read_only(&request)) {
+                log::debug!("Stale RPC connection, reconnecting: {}", e.message);
+                rpc = Self::connect(path).await?;
+                res = rpc.call(request.clone()).await;
+            }
+        }
+        match res {
+            Ok(response) => {
+                self.idle.put(path, rpc);
+                Ok(response)
+            }
+            Err(e) => {
+                if e.code.is_some() {
+                    self.idle.put(path, rpc);
+                }
+                Err(anyhow!("Error calling {:?}: {:?}", request, e))
+            }
+        }
+    }
+
+    async fn connect_raw(path: &str) -> Result<UnixStream, Error> {
+        UnixStream::connect(path).await.map_err(|e| anyhow!("Unable to connect to {}: {}", path, e))
+    }
+
+    /// Calls `method` with `params` and returns the `result`, for methods
+    /// `cln_rpc::ClnRpc` doesn't know because they aren't in its model, such
+    /// as `commando`.  Connections are pooled and retried as in `call`, with
+    /// `read_only` saying whether the call is safe to send twice.
+    pub async fn call_raw(&self, path: &str, method: &str, params: serde_json::Value, read_only: bool) -> Result<serde_json::Value, Error> {
+        let (mut stream, pooled) = match self.raw.take(path) {
+            Some(stream) => (stream, true),
+            None => (Self::connect_raw(path).await?, false),
+        };
+        let mut res = send_raw(&mut stream, method, &params).await;
+        if let Err(e) = &res {
+            if pooled && stale(e, read_only) {
+                log::debug!("Stale RPC connection, reconnecting: {}", e.message);
+                stream = Self::connect_raw(path).await?;
+                res = send_raw(&mut stream, method, &params).await;
+            }
+        }
+        if res.is_ok() || res.as_ref().err().and_then(|e| e.code).is_some() {
+            self.raw.put(path, stream);
+        }
+        res.map_err(|e| anyhow!("{}", e.message))
+    }
+}
+
+/// Whether a call failed because lightningd closed a pooled connection,
+/// and can be retried on a fresh one without repeating a side effect
+fn stale(e: &RpcError, read_only: bool) -> bool {
+    let unsent = e.message.starts_with("Error passing request");
+    let unread = e.message == "no response from lightningd" || e.message == "reading response from socket";
+    e.code.is_none() && (unsent || (unread && read_only))
+}
+
+/// Makes one JSON-RPC call on `stream`, failing with the same messages as
+/// `ClnRpc::call` so that `stale` applies to both.  Only one call is ever in
+/// flight on a connection, so whatever follows a response is whitespace.
+async fn send_raw(stream: &mut UnixStream, method: &str, params: &serde_json::Value) -> Result<serde_json::Value, RpcError> {
+    let rpc_error = |message: String| RpcError { code: None, message };
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    stream.write_all(request.to_string().as_bytes()).await
+        .map_err(|e| rpc_error(format!("Error passing request to lightningd: {}", e)))?;
+
+    let mut buf = vec![];
+    let mut response: serde_json::Value = loop {
+        let mut chunk = [0u8; 8192];
+        let n = stream.read(&mut chunk).await.map_err(|_| rpc_error("reading response from socket".to_string()))?;
+        if n == 0 {
+            return Err(rpc_error("no response from lightningd".to_string()))
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        match serde_json::from_slice(&buf) {
+            Ok(v) => break v,
+            Err(e) if e.is_eof() => continue,
+            Err(e) => return Err(rpc_error(format!("Malformed response from lightningd: {}", e))),
+        }
+    };
+    match response.get_mut("error") {
+        Some(error) => Err(serde_json::from_value(error.take())
+            .unwrap_or_else(|_| rpc_error("unknown error".to_string()))),
+        None => Ok(response["result"].take()),
+    }
+}
+
+/// Requests that are safe to send twice
+pub(crate) fn read_only(request: &Request) -> bool {
+    matches!(request,
+        Request::Getinfo(_)
+        | Request::ListFunds(_)
+        | Request::ListPeers(_)
+        | Request::ListNodes(_)
+        | Request::ListChannels(_)
+        | Request::ListDatastore(_)
+        | Request::GetRoute(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::UnixListener;
+
+    /// Answers every request on every connection with its own params,
+    /// counting connections
+    fn echo_server(path: &Path) -> Arc<AtomicUsize> {
+        let listener = UnixListener::bind(path).unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut chunk = [0u8; 8192];
+                    while let Ok(n) = stream.read(&mut chunk).await {
+                        if n == 0 {
+                            break
+                        }
+                        let request: serde_json::Value = serde_json::from_slice(&chunk[..n]).unwrap();
+                        let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": request["params"] });
+                        stream.write_all(format!("{}\n\n", response).as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        connections
+    }
+
+    #[tokio::test]
+    async fn raw_calls_reuse_pooled_connections() {
+        let path = std::env::temp_dir().join(format!("spaz-rpc-pool-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connections = echo_server(&path);
+        let pool = RpcPool::default();
+        let rpc_path = path.to_string_lossy().to_string();
+        for i in 0..3 {
+            let result = pool.call_raw(&rpc_path, "commando", json!({ "call": i }), false).await.unwrap();
+            assert_eq!(result, json!({ "call": i }));
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::{anyhow, Error, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::actions::ActionContext;
+use crate::budget::{Reservation, Spend};
+use crate::{random_in_range, Action, Amount, Channel, Node, Peer};
+
+/// Parameters to `spaz-run-once`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunOnceRequest {
+    pub action: String,
+    /// Pubkey for node and peer actions, short channel id for channel
+    /// actions.  Picked at random from the usual candidates if not given.
+    pub target: Option<String>,
+    /// msat for keysend and poke, sat for open.  Random within the
+    /// configured range if not given.
+    pub amount: Option<u64>,
+    /// Record the calls instead of making them, even if dry-run mode is off
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Ignore the target allow/deny lists, circuit breakers and budgets.
+    /// Protected channels and the network guard still apply.
+    #[serde(default)]
+    pub force: bool,
+}
+
+impl RunOnceRequest {
+    /// Accepts named params, or positional `action [target [amount]]`
+    pub fn from_params(params: serde_json::Value) -> Result<RunOnceRequest, Error> {
+        match params {
+            serde_json::Value::Object(_) => Ok(serde_json::from_value(params)?),
+            serde_json::Value::Array(a) => {
+                let mut args = a.into_iter();
+                let action = match args.next() {
+                    Some(serde_json::Value::String(s)) => s,
+                    _ => return Err(anyhow!("Usage: spaz-run-once <action> [target] [amount]")),
+                };
+                let target = args.next()
+                    .map(|t| t.as_str().map(|s| s.to_string()).ok_or_else(|| anyhow!("target must be a string, got {}", t)))
+                    .transpose()?;
+                let amount = args.next()
+                    .map(|a| match &a {
+                        serde_json::Value::String(s) => s.parse::<u64>().map_err(|e| anyhow!("Invalid amount {}: {}", s, e)),
+                        a => a.as_u64().ok_or_else(|| anyhow!("Invalid amount {}", a)),
+                    })
+                    .transpose()?;
+                Ok(RunOnceRequest { action, target, amount, ..Default::default() })
+            }
+            p => Err(anyhow!("Unexpected params: {}", p)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOnceStatus {
+    Ok,
+    Error,
+}
+
+/// What a single `spaz-run-once` did
+#[derive(Debug, Serialize)]
+pub struct RunOnceOutcome {
+    pub action: String,
+    pub status: RunOnceStatus,
+    pub dry_run: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+    /// Funding txid for opens, closing txid for closes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RunOnceOutcome {
+    pub fn new(action: &str, dry_run: bool) -> RunOnceOutcome {
+        RunOnceOutcome {
+            action: action.to_string(),
+            status: RunOnceStatus::Ok,
+            dry_run,
+            target: None,
+            amount: None,
+            txid: None,
+            payment_hash: None,
+            payment_status: None,
+            error: None,
+        }
+    }
+
+    pub fn failed(mut self, err: &Error) -> RunOnceOutcome {
+        self.status = RunOnceStatus::Error;
+        self.error = Some(format!("{:#}", err));
+        self
+    }
+}
+
+/// Runs `action` exactly once against a single target, ignoring its
+/// probability and the channel count limits.  Action failures end up in
+/// the outcome rather than the returned error.
+pub async fn run_once(ctx: &mut ActionContext, action: Action, request: &RunOnceRequest) -> RunOnceOutcome {
+    let mut outcome = RunOnceOutcome::new(action.name(), ctx.client.dry_run());
+    match run(ctx, action, request, &mut outcome).await {
+        Ok(()) => outcome,
+        Err(err) => {
+            log::warn!("run-once {} failed: {:#}", action, err);
+            outcome.failed(&err)
+        }
+    }
+}
+
+async fn run(ctx: &mut ActionContext, action: Action, request: &RunOnceRequest, outcome: &mut RunOnceOutcome) -> Result<(), Error> {
+    match action {
+        Action::Keysend => {
+            let (target, node) = pick(ctx, request, nodes(ctx, request.force).await?, |n| Some(n.nodeid.to_string()))?;
+            let amount = amount(ctx, request, ctx.config.keysend_amount_min_msat, ctx.config.keysend_amount_max_msat);
+            outcome.target = Some(target.clone());
+            outcome.amount = Some(amount);
+            let reservation = reserve(ctx, request, &ActionContext::payment_amounts(amount), &target)?;
+            let res = ctx.client.keysend_node(node.nodeid, Amount::from_msat(amount)).await;
+            let params = res.as_ref().map(|p| json!({ "payment_hash": p.payment_hash.to_string() })).unwrap_or_default();
+            ctx.record_target(&target, Some(amount), params, &res);
+            let payment = res?;
+            let sent = payment.amount_sent_msat.msat();
+            ctx.settle(reservation, &[(Spend::SentMsat, sent), (Spend::FeesMsat, sent.saturating_sub(payment.amount_msat.msat()))]);
+            outcome.payment_hash = Some(payment.payment_hash.to_string());
+            outcome.payment_status = serde_json::to_value(payment.status)?.as_str().map(|s| s.to_string());
+        }
+        Action::Poke => {
+            let (target, node) = pick(ctx, request, nodes(ctx, request.force).await?, |n| Some(n.nodeid.to_string()))?;
+            let amount = amount(ctx, request, ctx.config.poke_amount_min_msat, ctx.config.poke_amount_max_msat);
+            outcome.target = Some(target.clone());
+            outcome.amount = Some(amount);
+            let reservation = reserve(ctx, request, &ActionContext::payment_amounts(amount), &target)?;
+            let res = ctx.client.poke_node(node.nodeid, amount, &mut ctx.rng).await;
+            ctx.record_target(&target, Some(amount), serde_json::Value::Null, &res);
+            let fees_msat = res?;
+            ctx.settle(reservation, &[(Spend::FeesMsat, fees_msat)]);
+        }
+        Action::Open => {
+            let (target, node) = pick(ctx, request, nodes(ctx, request.force).await?, |n| Some(n.nodeid.to_string()))?;
+            let amount = amount(ctx, request, ctx.config.open_amount_min_sat, ctx.config.open_amount_max_sat);
+            outcome.target = Some(target.clone());
+            outcome.amount = Some(amount);
+            let reservation = reserve(ctx, request, &[(Spend::OpenSat, amount)], &target)?;
+            let res = ctx.client.open_channel_to_node(node, amount).await;
+            let params = res.as_ref().map(|txid| json!({ "txid": txid })).unwrap_or_default();
+            ctx.record_target(&target, Some(amount), params, &res);
+            let txid = res?;
+            ctx.settle(reservation, &[(Spend::OpenSat, amount)]);
+            outcome.txid = Some(txid).filter(|t| !t.is_empty());
+        }
+        Action::Close => {
+            let (target, _) = pick(ctx, request, channels(ctx, request.force).await?, |c| c.short_channel_id.clone())?;
+            outcome.target = Some(target.clone());
+            let res = ctx.client.close_channel(&target).await;
+            let params = res.as_ref().map(|txid| json!({ "txid": txid })).unwrap_or_default();
+            ctx.record_target(&target, None, params, &res);
+            outcome.txid = res?;
+            ctx.record_close();
+        }
+        Action::Fee => {
+            let (target, _) = pick(ctx, request, channels(ctx, request.force).await?, |c| c.short_channel_id.clone())?;
+            outcome.target = Some(target.clone());
+            let ppm_range = (ctx.config.fee_ppm_min, ctx.config.fee_ppm_max);
+            let base_range = (ctx.config.fee_base_min_msat, ctx.config.fee_base_max_msat);
+            let res = ctx.client.randomize_fee(&target, ppm_range, base_range, &mut ctx.rng).await;
+            let params = res.as_ref().map(|(ppm, base)| json!({ "fee_ppm": ppm, "fee_base_msat": base })).unwrap_or_default();
+            ctx.record_target(&target, None, params, &res);
+            res?;
+        }
+        Action::Disconnect => {
+            let (target, peer) = pick(ctx, request, peers(ctx, request.force).await?, |p| Some(p.id.to_string()))?;
+            outcome.target = Some(target.clone());
+            let res = ctx.client.disconnect_peer(peer.id).await;
+            ctx.record_target(&target, None, serde_json::Value::Null, &res);
+            res?;
+        }
+        Action::Ping => {
+            let (target, peer) = pick(ctx, request, peers(ctx, request.force).await?, |p| Some(p.id.to_string()))?;
+            outcome.target = Some(target.clone());
+            let res = ctx.client.random_ping_peer(peer.id, &mut ctx.rng).await;
+            ctx.record_target(&target, None, serde_json::Value::Null, &res);
+            res?;
+        }
+    }
+    Ok(())
+}
+
+async fn nodes(ctx: &ActionContext, force: bool) -> Result<Vec<Node>, Error> {
+    if force {
+        ctx.client.list_nodes().await
+    } else {
+        ctx.nodes().await
+    }
+}
+
+async fn peers(ctx: &ActionContext, force: bool) -> Result<Vec<Peer>, Error> {
+    let peers = if force { ctx.client.list_peers().await? } else { ctx.peers().await? };
+    Ok(peers.into_iter().filter(|p| p.connected).collect())
+}
+
+async fn channels(ctx: &ActionContext, force: bool) -> Result<Vec<Channel>, Error> {
+    if !force {
+        return ctx.channels().await
+    }
+    let protected = ctx.protected_channels().await?;
+    Ok(ctx.client.list_channels().await?
+        .into_iter()
+        .filter(|c| c.short_channel_id.as_ref().map(|scid| !protected.contains(scid)).unwrap_or(true))
+        .collect())
+}
+
+/// The requested target if it is among `candidates`, otherwise a random one
+fn pick<T>(
+    ctx: &mut ActionContext,
+    request: &RunOnceRequest,
+    candidates: Vec<T>,
+    id: impl Fn(&T) -> Option<String>,
+) -> Result<(String, T), Error> {
+    let mut candidates: Vec<(String, T)> = candidates.into_iter()
+        .filter_map(|c| id(&c).map(|i| (i, c)))
+        .collect();
+    let (target, candidate) = match &request.target {
+        Some(target) => candidates.into_iter()
+            .find(|(i, _)| i.eq_ignore_ascii_case(target))
+            .ok_or_else(|| anyhow!("{} is not a target for {}: unknown, filtered out, protected or disconnected", target, ctx.action))?,
+        None if candidates.is_empty() => return Err(anyhow!("No targets available for {}", ctx.action)),
+        None => {
+            let index = ctx.rng.gen_range(0, candidates.len());
+            candidates.swap_remove(index)
+        }
+    };
+    if !request.force && ctx.target_suspended(&target) {
+        return Err(anyhow!("{} is suspended for {} by its circuit breaker", target, ctx.action))
+    }
+    Ok((target, candidate))
+}
+
+fn amount(ctx: &mut ActionContext, request: &RunOnceRequest, min: u64, max: u64) -> u64 {
+    request.amount.unwrap_or_else(|| random_in_range(&mut ctx.rng, min, max))
+}
+
+/// Reserves `amounts` against the budgets, or just holds them when forced
+/// so that what a forced run spends is still charged
+fn reserve(ctx: &ActionContext, request: &RunOnceRequest, amounts: &[(Spend, u64)], target: &str) -> Result<Reservation, Error> {
+    if request.force {
+        return Ok(ctx.budget.hold(amounts))
+    }
+    ctx.reserve(amounts, target).ok_or_else(|| anyhow!("Budget exhausted for {}", target))
+}
@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Error, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::MAX_SECS;
+
+/// How the delay between two runs of an action is chosen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    /// Every `interval`, plus or minus `jitter * interval`
+    Fixed,
+    /// Exponentially distributed delays averaging `interval`, so runs form a Poisson process
+    Poisson,
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Schedule, Error> {
+        match s.trim().to_lowercase().as_str() {
+            "fixed" => Ok(Schedule::Fixed),
+            "poisson" | "exponential" => Ok(Schedule::Poisson),
+            o => Err(anyhow!("Unknown schedule {}, expected fixed or poisson", o)),
+        }
+    }
+}
+
+impl Schedule {
+    /// Delay until the next run for a mean interval of `interval_secs`.
+    /// `jitter` is a fraction of the interval and only applies to `Fixed`.
+    /// Delays are capped at `MAX_SECS`, which is also what a NaN interval
+    /// gets: waiting too long beats hammering the node.
+    pub fn next_delay<R: Rng>(&self, interval_secs: f64, jitter: f64, rng: &mut R) -> Duration {
+        let secs = match self {
+            Schedule::Fixed => {
+                let offset = jitter * (rng.gen::<f64>() * 2.0 - 1.0);
+                interval_secs * (1.0 + offset)
+            }
+            Schedule::Poisson => {
+                // gen::<f64>() is in [0, 1), so 1 - u is never zero
+                let u = 1.0 - rng.gen::<f64>();
+                -u.ln() * interval_secs
+            }
+        };
+        Duration::try_from_secs_f64(secs.clamp(0.0, MAX_SECS)).unwrap_or(Duration::from_secs_f64(MAX_SECS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn fixed_delays_stay_within_jitter() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let delay = Schedule::Fixed.next_delay(10.0, 0.2, &mut rng).as_secs_f64();
+            assert!((8.0..=12.0).contains(&delay), "{} out of bounds", delay);
+        }
+        assert_eq!(Schedule::Fixed.next_delay(10.0, 0.0, &mut rng), Duration::from_secs(10));
+        // Full jitter can reach zero but never goes negative
+        for _ in 0..1000 {
+            assert!(Schedule::Fixed.next_delay(10.0, 1.0, &mut rng) <= Duration::from_secs(20));
+        }
+    }
+
+    #[test]
+    fn poisson_delays_average_the_interval() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let total: f64 = (0..10_000).map(|_| Schedule::Poisson.next_delay(10.0, 0.5, &mut rng).as_secs_f64()).sum();
+        let mean = total / 10_000.0;
+        assert!((9.0..=11.0).contains(&mean), "mean {}", mean);
+    }
+
+    #[test]
+    fn degenerate_intervals_dont_panic_or_spin() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let max = Duration::from_secs_f64(MAX_SECS);
+        for schedule in [Schedule::Fixed, Schedule::Poisson] {
+            assert_eq!(schedule.next_delay(0.0, 0.5, &mut rng), Duration::ZERO);
+            assert_eq!(schedule.next_delay(-5.0, 0.5, &mut rng), Duration::ZERO);
+            assert_eq!(schedule.next_delay(f64::NAN, 0.5, &mut rng), max);
+            assert_eq!(schedule.next_delay(f64::INFINITY, 0.5, &mut rng), max);
+            assert_eq!(schedule.next_delay(1e300, 0.5, &mut rng), max);
+        }
+    }
+}
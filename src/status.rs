@@ -0,0 +1,93 @@
+use anyhow::Error;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LastError {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub message: String,
+}
+
+/// Counters for one action since the plugin started
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ActionStats {
+    pub attempts: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// Runs abandoned by `stop-spazzing`
+    pub cancelled: u64,
+    /// Runs not attempted because the network check refused them.  Not
+    /// counted in `attempts`.
+    pub skipped: u64,
+    pub last_error: Option<LastError>,
+    /// Unix timestamp in seconds of the next scheduled run
+    pub next_run: Option<u64>,
+}
+
+/// Live counters behind `spaz-status`
+pub struct Stats {
+    started: Instant,
+    actions: Mutex<BTreeMap<String, ActionStats>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats { started: Instant::now(), actions: Mutex::new(BTreeMap::new()) }
+    }
+}
+
+fn unix_time(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Stats {
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn scheduled(&self, action: &str, delay: Duration) {
+        let mut actions = self.actions.lock().unwrap();
+        actions.entry(action.to_string()).or_default().next_run = Some(unix_time(SystemTime::now() + delay));
+    }
+
+    pub fn record<T>(&self, action: &str, result: &Result<T, Error>) {
+        let mut actions = self.actions.lock().unwrap();
+        let stats = actions.entry(action.to_string()).or_default();
+        stats.attempts += 1;
+        match result {
+            Ok(_) => stats.successes += 1,
+            Err(err) => {
+                stats.failures += 1;
+                stats.last_error = Some(LastError {
+                    timestamp: unix_time(SystemTime::now()),
+                    message: format!("{:#}", err),
+                });
+            }
+        }
+    }
+
+    /// Records a run that was never attempted, keeping `reason` as the last error
+    pub fn record_skipped(&self, action: &str, reason: &Error) {
+        let mut actions = self.actions.lock().unwrap();
+        let stats = actions.entry(action.to_string()).or_default();
+        stats.skipped += 1;
+        stats.last_error = Some(LastError {
+            timestamp: unix_time(SystemTime::now()),
+            message: format!("{:#}", reason),
+        });
+    }
+
+    pub fn record_cancelled(&self, action: &str) {
+        let mut actions = self.actions.lock().unwrap();
+        let stats = actions.entry(action.to_string()).or_default();
+        stats.attempts += 1;
+        stats.cancelled += 1;
+    }
+
+    pub fn get(&self, action: &str) -> ActionStats {
+        self.actions.lock().unwrap().get(action).cloned().unwrap_or_default()
+    }
+}